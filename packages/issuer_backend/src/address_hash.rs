@@ -0,0 +1,116 @@
+use crate::eth::{convert_to_eip55, keccak256, EthAddress};
+use crate::random::init_random_secret;
+use crate::{
+    ADDRESS_PEPPER, ETH_PRINCIPAL, PRINCIPAL_ADDRESSES, PRINCIPAL_PRIMARY, UNLINK_COOLDOWNS,
+};
+
+/// Generates the secret pepper used to hash Ethereum addresses, unless one has been generated
+/// already, see `random::init_random_secret`.
+pub fn init_address_pepper() {
+    init_random_secret(
+        "address pepper",
+        || ADDRESS_PEPPER.with_borrow(|p| *p.get() != [0; 32]),
+        |pepper| {
+            ADDRESS_PEPPER.with_borrow_mut(|p| {
+                p.set(pepper).expect("Failed to save pepper");
+            });
+        },
+    );
+}
+
+/// Computes the keyed hash of an Ethereum address, `keccak256(pepper || address)`.
+///
+/// Plain hashes of addresses can be reversed by hashing all known Ethereum addresses. The secret
+/// pepper prevents this for anyone without access to it. Keccak is not vulnerable to length
+/// extension attacks, which makes prefixing the key a secure construction.
+fn peppered_hash(address: &EthAddress) -> Result<[u8; 32], String> {
+    let pepper = ADDRESS_PEPPER.with_borrow(|p| *p.get());
+    if pepper == [0; 32] {
+        return Err("Address pepper not initialized".to_string());
+    }
+
+    let mut bytes = Vec::with_capacity(32 + 20);
+    bytes.extend_from_slice(&pepper);
+    bytes.extend_from_slice(&address.as_byte_array());
+    Ok(keccak256(&bytes))
+}
+
+/// Returns the hash under which an Ethereum address is stored.
+///
+/// Addresses used to be stored under their plain keccak hash. If any entries are found under the
+/// plain hash, they are re-keyed to the peppered hash before it is returned.
+pub fn address_hash(address: &EthAddress) -> Result<[u8; 32], String> {
+    let address_hash = peppered_hash(address)?;
+    let legacy_hash = address.as_hash();
+
+    if let Some(principal) = ETH_PRINCIPAL.with_borrow_mut(|e| e.remove(&legacy_hash)) {
+        ETH_PRINCIPAL.with_borrow_mut(|e| e.insert(address_hash, principal));
+
+        if let Some(mut record) =
+            PRINCIPAL_ADDRESSES.with_borrow_mut(|a| a.remove(&(principal, legacy_hash)))
+        {
+            record.address_hash = address_hash;
+            PRINCIPAL_ADDRESSES.with_borrow_mut(|a| a.insert((principal, address_hash), record));
+        }
+
+        PRINCIPAL_PRIMARY.with_borrow_mut(|p| {
+            if p.get(&principal) == Some(legacy_hash) {
+                p.insert(principal, address_hash);
+            }
+        });
+    }
+
+    if let Some(cooldown) = UNLINK_COOLDOWNS.with_borrow_mut(|c| c.remove(&legacy_hash)) {
+        UNLINK_COOLDOWNS.with_borrow_mut(|c| c.insert(address_hash, cooldown));
+    }
+
+    Ok(address_hash)
+}
+
+/// Keystream used to encrypt the address stored under `address_hash`, derived from the pepper.
+fn address_keystream(address_hash: &[u8; 32]) -> Result<[u8; 20], String> {
+    let pepper = ADDRESS_PEPPER.with_borrow(|p| *p.get());
+    if pepper == [0; 32] {
+        return Err("Address pepper not initialized".to_string());
+    }
+
+    let mut bytes = Vec::with_capacity(18 + 32 + 32);
+    bytes.extend_from_slice(b"address-encryption");
+    bytes.extend_from_slice(&pepper);
+    bytes.extend_from_slice(address_hash);
+
+    let mut keystream = [0; 20];
+    keystream.copy_from_slice(&keccak256(&bytes)[..20]);
+    Ok(keystream)
+}
+
+/// Encrypts an Ethereum address so that its score can be refreshed without user interaction, while
+/// not storing the address in plain text. Like the address hash, the encryption relies on the
+/// secret pepper.
+pub fn encrypt_address(address: &EthAddress, address_hash: &[u8; 32]) -> Result<[u8; 20], String> {
+    let keystream = address_keystream(address_hash)?;
+    let mut encrypted = address.as_byte_array();
+    for (byte, key) in encrypted.iter_mut().zip(keystream) {
+        *byte ^= key;
+    }
+    Ok(encrypted)
+}
+
+/// Decrypts an Ethereum address encrypted with `encrypt_address`, making sure it matches the
+/// address hash.
+pub fn decrypt_address(
+    encrypted: &[u8; 20],
+    address_hash: &[u8; 32],
+) -> Result<EthAddress, String> {
+    let keystream = address_keystream(address_hash)?;
+    let mut bytes = *encrypted;
+    for (byte, key) in bytes.iter_mut().zip(keystream) {
+        *byte ^= key;
+    }
+
+    let address = EthAddress::new(&convert_to_eip55(&hex::encode(bytes))?)?;
+    if peppered_hash(&address)? != *address_hash {
+        return Err("Decrypted address does not match address hash".to_string());
+    }
+    Ok(address)
+}
@@ -1,9 +1,10 @@
-use crate::{ASSETS, SIGNATURES};
-use asset_util::{collect_assets, CertifiedAssets};
+use crate::{ASSETS, SETTINGS, SIGNATURES};
+use asset_util::{collect_assets, Asset, CertifiedAssets, ContentEncoding, ContentType};
 use canister_sig_util::signature_map::LABEL_SIG;
 use ic_cdk::api::set_certified_data;
 use ic_certification::{fork_hash, labeled_hash};
 use include_dir::{include_dir, Dir};
+use serde_json::json;
 
 static ASSET_DIR: Dir<'_> = include_dir!("$CARGO_MANIFEST_DIR/../issuer_frontend/dist");
 
@@ -29,8 +30,33 @@ fn fixup_html(html: &str) -> String {
       )
 }
 
+/// Lists the frontend hostnames configured in settings as alternative origins, allowing Internet
+/// Identity to issue the same principals to frontends served from custom domains. See
+/// [Alternative Frontend Origins](https://internetcomputer.org/docs/current/developer-docs/identity/internet-identity/alternative-origins).
+fn alternative_origins_asset() -> Asset {
+    let origins = SETTINGS.with_borrow(|settings_opt| {
+        settings_opt
+            .as_ref()
+            .expect("Settings should be initialized")
+            .frontend_hostnames
+            .clone()
+    });
+
+    Asset {
+        url_path: "/.well-known/ii-alternative-origins".to_string(),
+        content: json!({ "alternativeOrigins": origins })
+            .to_string()
+            .into_bytes(),
+        encoding: ContentEncoding::Identity,
+        content_type: ContentType::JSON,
+    }
+}
+
+/// Certifies the frontend assets and the alternative origins generated from settings. Needs to be
+/// called again when the frontend hostnames in settings change.
 pub fn init_assets() {
-    let assets = collect_assets(&ASSET_DIR, Some(fixup_html));
+    let mut assets = collect_assets(&ASSET_DIR, Some(fixup_html));
+    assets.push(alternative_origins_asset());
     ASSETS.with_borrow_mut(|certified_assets| {
         *certified_assets = CertifiedAssets::certify_assets(assets, &crate::http::static_headers());
     });
@@ -0,0 +1,9 @@
+use ic_cdk::api::is_controller;
+
+pub fn controller() -> Result<(), String> {
+    if !is_controller(&ic_cdk::caller()) {
+        return Err("Not authorized".to_string());
+    }
+
+    Ok(())
+}
@@ -1,4 +1,5 @@
 use candid::Principal;
+use identity_core::common::Timestamp;
 use k256::ecdsa::{RecoveryId, Signature, VerifyingKey};
 use std::fmt;
 use tiny_keccak::{Hasher, Keccak};
@@ -9,9 +10,13 @@ pub enum EthError {
     DecodingError(hex::FromHexError),
     SignatureFormatError(String),
     InvalidSignature,
+    MalleableSignature,
     InvalidRecoveryId,
     PublicKeyRecoveryFailure,
     Eip55Error(String),
+    SiweFormatError(String),
+    SiweValidationError(String),
+    Eip712Error(String),
 }
 
 impl From<hex::FromHexError> for EthError {
@@ -27,11 +32,15 @@ impl fmt::Display for EthError {
             EthError::DecodingError(e) => write!(f, "Decoding error: {}", e),
             EthError::SignatureFormatError(e) => write!(f, "Signature format error: {}", e),
             EthError::InvalidSignature => write!(f, "Invalid signature"),
+            EthError::MalleableSignature => write!(f, "Malleable signature, s value too high"),
             EthError::InvalidRecoveryId => write!(f, "Invalid recovery ID"),
             EthError::PublicKeyRecoveryFailure => {
                 write!(f, "Public key recovery failure")
             }
             EthError::Eip55Error(e) => write!(f, "EIP-55 error: {}", e),
+            EthError::SiweFormatError(e) => write!(f, "SIWE format error: {}", e),
+            EthError::SiweValidationError(e) => write!(f, "SIWE validation error: {}", e),
+            EthError::Eip712Error(e) => write!(f, "EIP-712 error: {}", e),
         }
     }
 }
@@ -46,7 +55,7 @@ impl From<EthError> for String {
 ///
 /// This struct ensures that the contained Ethereum address string is valid according to Ethereum standards.
 /// It checks for correct length, hex encoding, and EIP-55 encoding.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct EthAddress(String);
 
 impl EthAddress {
@@ -92,6 +101,8 @@ impl EthAddress {
         array
     }
 
+    /// Returns the plain keccak hash of the address. Only used to find entries stored before
+    /// address hashes were peppered, see `address_hash::address_hash`.
     pub fn as_hash(&self) -> [u8; 32] {
         let bytes = self.as_byte_array();
         let mut keccak256 = [0; 32];
@@ -105,26 +116,64 @@ impl EthAddress {
 /// Represents an Ethereum signature with validation.
 ///
 /// This struct ensures that the contained Ethereum signature string is valid.
-/// It checks for correct length and hex encoding.
+/// It checks for correct length and hex encoding, and normalizes the signature to its canonical
+/// 65-byte form with a recovery byte `v` of 27 or 28.
 #[derive(Debug)]
 pub struct EthSignature(String);
 
 impl EthSignature {
-    /// Creates a new `EthSignature` after validating the Ethereum signature format.
+    /// Creates a new `EthSignature` after validating and normalizing the Ethereum signature.
+    ///
+    /// The signature must start with '0x' and be either:
+    /// - 65 to 96 bytes long, `r || s || v`, where `v` is 0, 1, 27, 28 or EIP-155 encoded as
+    ///   `chain_id * 2 + 35 + y_parity`. `v` is a big-endian integer taking up the bytes after `s`,
+    ///   as it no longer fits in a single byte for chains with a large chain ID.
+    /// - 64 bytes long, `r || y_parity_and_s`, the compact form specified by
+    ///   [EIP-2098](https://eips.ethereum.org/EIPS/eip-2098).
     ///
-    /// The signature must start with '0x' and be 132 characters long.
+    /// Signatures with a high `s` value are malleable and rejected, see
+    /// [EIP-2](https://eips.ethereum.org/EIPS/eip-2).
     ///
     /// # Arguments
     /// * `signature` - A string slice representing the Ethereum signature.
     pub fn new(signature: &str) -> Result<EthSignature, EthError> {
-        if !signature.starts_with("0x") || signature.len() != 132 {
-            return Err(EthError::SignatureFormatError(String::from(
-                "Must start with '0x' and be 132 characters long",
-            )));
+        let signature = signature
+            .strip_prefix("0x")
+            .ok_or_else(|| EthError::SignatureFormatError(String::from("Must start with '0x'")))?;
+
+        let bytes = hex::decode(signature).map_err(EthError::DecodingError)?;
+
+        let mut r_s = [0u8; 64];
+        let y_parity = match bytes.len() {
+            64 => {
+                // The top bit of s holds the y parity.
+                r_s.copy_from_slice(&bytes);
+                let y_parity = r_s[32] >> 7;
+                r_s[32] &= 0x7f;
+                y_parity
+            }
+            65..=96 => {
+                r_s.copy_from_slice(&bytes[..64]);
+                normalize_recovery_value(&bytes[64..])?
+            }
+            _ => {
+                return Err(EthError::SignatureFormatError(String::from(
+                    "Must be 64 to 96 bytes long",
+                )))
+            }
+        };
+
+        let ecdsa_signature =
+            Signature::from_slice(&r_s).map_err(|_| EthError::InvalidSignature)?;
+        if ecdsa_signature.normalize_s().is_some() {
+            return Err(EthError::MalleableSignature);
         }
 
-        hex::decode(&signature[2..]).map_err(EthError::DecodingError)?;
-        Ok(EthSignature(signature.to_owned()))
+        Ok(EthSignature(format!(
+            "0x{}{:02x}",
+            hex::encode(r_s),
+            27 + y_parity
+        )))
     }
 
     /// Returns a string slice of the Ethereum signature.
@@ -148,27 +197,47 @@ impl EthSignature {
     }
 }
 
-/// Recovers an Ethereum address from a given message and signature.
+/// Converts the recovery value `v` of a signature, a big-endian integer, to the y parity of the
+/// signature.
+fn normalize_recovery_value(v: &[u8]) -> Result<u8, EthError> {
+    let first_non_zero = v.iter().position(|byte| *byte != 0).unwrap_or(v.len());
+    match v[first_non_zero..] {
+        [] => Ok(0),
+        [v] => match v {
+            1 => Ok(v),
+            27 | 28 => Ok(v - 27),
+            35.. => Ok((v - 35) % 2),
+            _ => Err(EthError::InvalidRecoveryId),
+        },
+        // Values of more than one byte are larger than 35, subtracting the odd 35 flips the parity.
+        [.., last] => Ok((last ^ 1) & 1),
+    }
+}
+
+/// Recovers an Ethereum address from a given message hash and signature, such as the EIP-191 hash
+/// of a message or the EIP-712 hash of typed data.
 ///
 /// # Parameters
 ///
-/// * `message` - The message that was signed.
+/// * `message_hash` - The 32-byte hash that was signed.
 /// * `signature` - The hex-encoded signature.
 ///
 /// # Returns
 ///
 /// The recovered Ethereum address if successful, or an error.
-pub fn recover_eth_address(message: &str, signature: &EthSignature) -> Result<String, EthError> {
-    let message_hash = eip191_hash(message);
+pub fn recover_eth_address_from_hash(
+    message_hash: &[u8; 32],
+    signature: &EthSignature,
+) -> Result<String, EthError> {
     let signature_bytes = signature.as_bytes();
 
     let recovery_id =
-        RecoveryId::try_from(signature_bytes[64] % 27).map_err(|_| EthError::InvalidRecoveryId)?;
+        RecoveryId::try_from(signature_bytes[64] - 27).map_err(|_| EthError::InvalidRecoveryId)?;
 
     let signature =
         Signature::from_slice(&signature_bytes[..64]).map_err(|_| EthError::InvalidSignature)?;
 
-    let verifying_key = VerifyingKey::recover_from_prehash(&message_hash, &signature, recovery_id)
+    let verifying_key = VerifyingKey::recover_from_prehash(message_hash, &signature, recovery_id)
         .map_err(|_| EthError::PublicKeyRecoveryFailure)?;
 
     let address = derive_eth_address_from_public_key(&verifying_key)?;
@@ -176,6 +245,15 @@ pub fn recover_eth_address(message: &str, signature: &EthSignature) -> Result<St
     Ok(address)
 }
 
+/// Computes the keccak256 hash of the given bytes.
+pub fn keccak256(bytes: &[u8]) -> [u8; 32] {
+    let mut hash = [0; 32];
+    let mut hasher = Keccak::v256();
+    hasher.update(bytes);
+    hasher.finalize(&mut hash);
+    hash
+}
+
 /// Hashes a message using the EIP-191 standard. See [EIP-191 spec](https://eips.ethereum.org/EIPS/eip-191) for
 /// more information.
 ///
@@ -289,12 +367,685 @@ pub fn convert_to_eip55(address: &str) -> Result<String, EthError> {
     Ok(format!("0x{}", checksummed_addr))
 }
 
+/// The maximum time a signed link message, SIWE or EIP-712, may remain valid, counted from the time
+/// of verification.
+const LINK_MESSAGE_MAX_VALIDITY_NS: u64 = 10 * 60 * 1_000_000_000; // 10 minutes
+
+const SIWE_PREAMBLE_SUFFIX: &str = " wants you to sign in with your Ethereum account:";
+
+/// Represents a parsed Sign-In with Ethereum message. See [EIP-4361 spec](https://eips.ethereum.org/EIPS/eip-4361)
+/// for more information.
+///
+/// Timestamps are stored as nanoseconds since the Unix epoch to be comparable with `ic_cdk::api::time`.
+#[derive(Debug)]
+pub struct SiweMessage {
+    pub domain: String,
+    pub address: EthAddress,
+    pub statement: Option<String>,
+    pub uri: String,
+    pub version: String,
+    pub chain_id: u64,
+    pub nonce: String,
+    pub issued_at: u64,
+    pub expiration_time: Option<u64>,
+    pub not_before: Option<u64>,
+}
+
+impl SiweMessage {
+    /// Parses a SIWE message formatted according to EIP-4361.
+    ///
+    /// The optional `Request ID` and `Resources` fields are accepted but not retained.
+    ///
+    /// # Arguments
+    /// * `message` - The message as presented to and signed by the wallet.
+    pub fn parse(message: &str) -> Result<SiweMessage, EthError> {
+        let mut lines = message.split('\n');
+
+        let domain = lines
+            .next()
+            .and_then(|line| line.strip_suffix(SIWE_PREAMBLE_SUFFIX))
+            .filter(|domain| !domain.is_empty())
+            .ok_or_else(|| siwe_format_error("Missing preamble"))?
+            .to_string();
+
+        let address = EthAddress::new(
+            lines
+                .next()
+                .ok_or_else(|| siwe_format_error("Missing address"))?,
+        )?;
+
+        if lines.next() != Some("") {
+            return Err(siwe_format_error("Expected empty line after address"));
+        }
+
+        // The statement is optional. If present, it is followed by an empty line. Implementations
+        // differ in whether they emit an extra empty line when the statement is omitted.
+        let mut line = lines.next();
+        let mut statement = None;
+        match line {
+            Some("") => line = lines.next(),
+            Some(l) if !l.starts_with("URI: ") => {
+                statement = Some(l.to_string());
+                if lines.next() != Some("") {
+                    return Err(siwe_format_error("Expected empty line after statement"));
+                }
+                line = lines.next();
+            }
+            _ => {}
+        }
+
+        let uri = parse_siwe_field(line, "URI")?.to_string();
+        let version = parse_siwe_field(lines.next(), "Version")?.to_string();
+        let chain_id = parse_siwe_field(lines.next(), "Chain ID")?
+            .parse::<u64>()
+            .map_err(|_| siwe_format_error("Invalid chain ID"))?;
+
+        let nonce = parse_siwe_field(lines.next(), "Nonce")?.to_string();
+        if nonce.len() < 8 || !nonce.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Err(siwe_format_error(
+                "Nonce must be at least 8 alphanumeric characters",
+            ));
+        }
+
+        let issued_at = parse_siwe_timestamp(parse_siwe_field(lines.next(), "Issued At")?)?;
+
+        let mut expiration_time = None;
+        let mut not_before = None;
+        let mut line = lines.next();
+        if let Some(value) = line.and_then(|l| l.strip_prefix("Expiration Time: ")) {
+            expiration_time = Some(parse_siwe_timestamp(value)?);
+            line = lines.next();
+        }
+        if let Some(value) = line.and_then(|l| l.strip_prefix("Not Before: ")) {
+            not_before = Some(parse_siwe_timestamp(value)?);
+            line = lines.next();
+        }
+        if line.is_some_and(|l| l.starts_with("Request ID: ")) {
+            line = lines.next();
+        }
+        if line == Some("Resources:") {
+            line = lines.next();
+            while line.is_some_and(|l| l.starts_with("- ")) {
+                line = lines.next();
+            }
+        }
+        if line.is_some() {
+            return Err(siwe_format_error("Unexpected content at end of message"));
+        }
+
+        Ok(SiweMessage {
+            domain,
+            address,
+            statement,
+            uri,
+            version,
+            chain_id,
+            nonce,
+            issued_at,
+            expiration_time,
+            not_before,
+        })
+    }
+
+    /// Validates the SIWE message against the expected domain, URI, chain ID and statement, and
+    /// checks that the message is within its validity period.
+    ///
+    /// An expiration time is required and may not lie further in the future than
+    /// `LINK_MESSAGE_MAX_VALIDITY_NS`, this bounds the window in which a captured signature can be used.
+    ///
+    /// # Arguments
+    /// * `domain` - The domain the message is expected to be issued for.
+    /// * `uri` - The URI the message is expected to be issued for.
+    /// * `chain_id` - The chain ID the message is expected to be issued for.
+    /// * `statement` - The statement the user is expected to have signed.
+    /// * `now` - The current time in nanoseconds since the Unix epoch.
+    pub fn validate(
+        &self,
+        domain: &str,
+        uri: &str,
+        chain_id: u64,
+        statement: &str,
+        now: u64,
+    ) -> Result<(), EthError> {
+        if self.domain != domain {
+            return Err(siwe_validation_error("Domain mismatch"));
+        }
+
+        if self.uri != uri {
+            return Err(siwe_validation_error("URI mismatch"));
+        }
+
+        if self.version != "1" {
+            return Err(siwe_validation_error("Unsupported version"));
+        }
+
+        if self.chain_id != chain_id {
+            return Err(siwe_validation_error("Chain ID mismatch"));
+        }
+
+        if self.statement.as_deref() != Some(statement) {
+            return Err(siwe_validation_error("Statement mismatch"));
+        }
+
+        let expiration_time = self
+            .expiration_time
+            .ok_or_else(|| siwe_validation_error("Missing expiration time"))?;
+        if expiration_time <= now {
+            return Err(siwe_validation_error("Message expired"));
+        }
+        if expiration_time > now.saturating_add(LINK_MESSAGE_MAX_VALIDITY_NS) {
+            return Err(siwe_validation_error(
+                "Expiration time too far in the future",
+            ));
+        }
+
+        if self.issued_at >= expiration_time {
+            return Err(siwe_validation_error(
+                "Issued at must be before expiration time",
+            ));
+        }
+
+        if let Some(not_before) = self.not_before {
+            if not_before > now {
+                return Err(siwe_validation_error("Message not yet valid"));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn siwe_format_error(message: &str) -> EthError {
+    EthError::SiweFormatError(message.to_string())
+}
+
+fn siwe_validation_error(message: &str) -> EthError {
+    EthError::SiweValidationError(message.to_string())
+}
+
+fn parse_siwe_field<'a>(line: Option<&'a str>, name: &str) -> Result<&'a str, EthError> {
+    line.and_then(|l| l.strip_prefix(name))
+        .and_then(|l| l.strip_prefix(": "))
+        .ok_or_else(|| EthError::SiweFormatError(format!("Missing {}", name)))
+}
+
+/// Parses an RFC 3339 timestamp into nanoseconds since the Unix epoch.
+fn parse_siwe_timestamp(value: &str) -> Result<u64, EthError> {
+    let seconds = Timestamp::parse(value)
+        .map_err(|_| siwe_format_error("Invalid timestamp"))?
+        .to_unix();
+    let seconds =
+        u64::try_from(seconds).map_err(|_| siwe_format_error("Timestamp before Unix epoch"))?;
+    seconds
+        .checked_mul(1_000_000_000)
+        .ok_or_else(|| siwe_format_error("Timestamp out of range"))
+}
+
+///
+/// Create the SIWE statement to sign to link an Ethereum address to an Internet Computer principal.
+///
+pub fn create_siwe_statement(principal: &Principal) -> String {
+    format!(
+        "Sign in to link your Ethereum address to Internet Computer principal {}.",
+        principal.to_text()
+    )
+}
+
+///
+/// Create the SIWE statement to sign to unlink an Ethereum address from an Internet Computer principal.
+///
+pub fn create_siwe_unlink_statement(principal: &Principal) -> String {
+    format!(
+        "Sign in to unlink your Ethereum address from Internet Computer principal {}.",
+        principal.to_text()
+    )
+}
+
 ///
-/// Create a message to sign to link an Ethereum address to an Internet Computer principal.
+/// Create the SIWE statement to sign to transfer the linked Ethereum addresses of one Internet
+/// Computer principal to another.
 ///
-pub fn create_signing_message(address: &EthAddress, principal: &Principal) -> String {
+pub fn create_siwe_transfer_statement(from: &Principal, to: &Principal) -> String {
     format!(
-        "Sign this message to link your Ethereum address to your Internet Computer identity.\n\nEthereum address: {}\n\nInternet Computer principal: {}",
-        address.as_str(),
-        principal.to_text())
+        "Sign in to transfer your linked Ethereum addresses from Internet Computer principal {} to {}.",
+        from.to_text(),
+        to.to_text()
+    )
+}
+
+const EIP712_DOMAIN_TYPE: &str = "EIP712Domain(string name,string version)";
+const EIP712_DOMAIN_NAME: &str = "Gitcoin Passport Score Issuer";
+const EIP712_DOMAIN_VERSION: &str = "1";
+const LINK_IDENTITY_TYPE: &str =
+    "LinkIdentity(address wallet,string principal,uint256 nonce,uint256 expiry)";
+
+/// Represents the EIP-712 typed data signed to link an Ethereum address to an Internet Computer
+/// principal. See [EIP-712 spec](https://eips.ethereum.org/EIPS/eip-712) for more information.
+///
+/// The typed data is defined as follows:
+///
+/// ```text
+/// EIP712Domain(string name,string version)
+/// LinkIdentity(address wallet,string principal,uint256 nonce,uint256 expiry)
+/// ```
+///
+/// The domain has the name `Gitcoin Passport Score Issuer` and version `1`. The nonce is the hex
+/// encoded nonce issued by `link_nonce_create`, interpreted as a big-endian integer. The expiry is
+/// expressed in seconds since the Unix epoch.
+#[derive(Debug)]
+pub struct LinkIdentity {
+    pub wallet: EthAddress,
+    pub principal: Principal,
+    pub nonce: String,
+    pub expiry: u64,
+}
+
+impl LinkIdentity {
+    /// Checks that the typed data is within its validity period.
+    ///
+    /// # Arguments
+    /// * `now` - The current time in nanoseconds since the Unix epoch.
+    pub fn validate(&self, now: u64) -> Result<(), EthError> {
+        let expiry = self.expiry.saturating_mul(1_000_000_000);
+        if expiry <= now {
+            return Err(EthError::Eip712Error("Expired".to_string()));
+        }
+        if expiry > now.saturating_add(LINK_MESSAGE_MAX_VALIDITY_NS) {
+            return Err(EthError::Eip712Error(
+                "Expiry too far in the future".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Computes the EIP-712 hash of the typed data, ready to be signed or recovered from.
+    pub fn eip712_hash(&self) -> Result<[u8; 32], EthError> {
+        let mut bytes = Vec::with_capacity(2 + 32 + 32);
+        bytes.extend_from_slice(b"\x19\x01");
+        bytes.extend_from_slice(&eip712_domain_separator());
+        bytes.extend_from_slice(&self.struct_hash()?);
+        Ok(keccak256(&bytes))
+    }
+
+    fn struct_hash(&self) -> Result<[u8; 32], EthError> {
+        let nonce = hex::decode(&self.nonce)?;
+        if nonce.is_empty() || nonce.len() > 32 {
+            return Err(EthError::Eip712Error("Invalid nonce".to_string()));
+        }
+
+        let mut bytes = Vec::with_capacity(5 * 32);
+        bytes.extend_from_slice(&keccak256(LINK_IDENTITY_TYPE.as_bytes()));
+        bytes.extend_from_slice(&[0; 12]);
+        bytes.extend_from_slice(&self.wallet.as_byte_array());
+        bytes.extend_from_slice(&keccak256(self.principal.to_text().as_bytes()));
+        bytes.extend_from_slice(&vec![0; 32 - nonce.len()]);
+        bytes.extend_from_slice(&nonce);
+        bytes.extend_from_slice(&[0; 24]);
+        bytes.extend_from_slice(&self.expiry.to_be_bytes());
+        Ok(keccak256(&bytes))
+    }
+}
+
+fn eip712_domain_separator() -> [u8; 32] {
+    let mut bytes = Vec::with_capacity(3 * 32);
+    bytes.extend_from_slice(&keccak256(EIP712_DOMAIN_TYPE.as_bytes()));
+    bytes.extend_from_slice(&keccak256(EIP712_DOMAIN_NAME.as_bytes()));
+    bytes.extend_from_slice(&keccak256(EIP712_DOMAIN_VERSION.as_bytes()));
+    keccak256(&bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use k256::ecdsa::SigningKey;
+
+    const ADDRESS: &str = "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed";
+    const DOMAIN: &str = "example.com";
+    const URI: &str = "https://example.com";
+    const STATEMENT: &str = "Sign in to link your Ethereum address.";
+
+    // 2024-05-01T00:00:00Z
+    const ISSUED_AT_NS: u64 = 1_714_521_600 * 1_000_000_000;
+    const NOW_NS: u64 = ISSUED_AT_NS + 60 * 1_000_000_000;
+
+    fn format_siwe_message(statement: Option<&str>, optional_fields: &str) -> String {
+        let statement = statement
+            .map(|statement| format!("{}\n\n", statement))
+            .unwrap_or_default();
+        format!(
+            "{DOMAIN} wants you to sign in with your Ethereum account:\n\
+            {ADDRESS}\n\
+            \n\
+            {statement}\
+            URI: {URI}\n\
+            Version: 1\n\
+            Chain ID: 1\n\
+            Nonce: 0123456789abcdef\n\
+            Issued At: 2024-05-01T00:00:00Z{optional_fields}"
+        )
+    }
+
+    fn valid_siwe_message() -> SiweMessage {
+        SiweMessage::parse(&format_siwe_message(
+            Some(STATEMENT),
+            "\nExpiration Time: 2024-05-01T00:05:00Z",
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn siwe_parse_all_fields() {
+        let message = format_siwe_message(
+            Some(STATEMENT),
+            "\nExpiration Time: 2024-05-01T00:05:00Z\n\
+            Not Before: 2024-05-01T00:00:30Z\n\
+            Request ID: 42\n\
+            Resources:\n\
+            - https://example.com/a\n\
+            - https://example.com/b",
+        );
+        let siwe_message = SiweMessage::parse(&message).unwrap();
+
+        assert_eq!(siwe_message.domain, DOMAIN);
+        assert_eq!(siwe_message.address.as_str(), ADDRESS);
+        assert_eq!(siwe_message.statement.as_deref(), Some(STATEMENT));
+        assert_eq!(siwe_message.uri, URI);
+        assert_eq!(siwe_message.version, "1");
+        assert_eq!(siwe_message.chain_id, 1);
+        assert_eq!(siwe_message.nonce, "0123456789abcdef");
+        assert_eq!(siwe_message.issued_at, ISSUED_AT_NS);
+        assert_eq!(
+            siwe_message.expiration_time,
+            Some(ISSUED_AT_NS + 300 * 1_000_000_000)
+        );
+        assert_eq!(
+            siwe_message.not_before,
+            Some(ISSUED_AT_NS + 30 * 1_000_000_000)
+        );
+    }
+
+    #[test]
+    fn siwe_parse_without_statement() {
+        let message = format_siwe_message(None, "");
+        let siwe_message = SiweMessage::parse(&message).unwrap();
+        assert_eq!(siwe_message.statement, None);
+        assert_eq!(siwe_message.expiration_time, None);
+
+        // Some implementations emit an extra empty line when the statement is omitted.
+        let message = message.replace("\n\nURI: ", "\n\n\nURI: ");
+        let siwe_message = SiweMessage::parse(&message).unwrap();
+        assert_eq!(siwe_message.statement, None);
+    }
+
+    #[test]
+    fn siwe_parse_rejects_malformed_messages() {
+        let message = format_siwe_message(Some(STATEMENT), "");
+        let invalid = [
+            message.replace(" wants you to sign in", " wants you to log in"),
+            message.replace(ADDRESS, &ADDRESS.to_lowercase().replace("0x5a", "0x5A")),
+            message.replace(&format!("{}\n\n", STATEMENT), &format!("{}\n", STATEMENT)),
+            message.replace("Chain ID: 1", "Chain ID: one"),
+            message.replace("Chain ID: 1", "Chain ID: -1"),
+            message.replace("Nonce: 0123456789abcdef", "Nonce: 0123"),
+            message.replace("Nonce: 0123456789abcdef", "Nonce: 0123-4567-89ab"),
+            message.replace("Version: 1\n", ""),
+            message.replace("2024-05-01T00:00:00Z", "yesterday"),
+            message.replace("2024-05-01T00:00:00Z", "1969-12-31T23:59:59Z"),
+            format!("{}\nUnexpected: field", message),
+        ];
+
+        for message in invalid {
+            assert!(
+                matches!(
+                    SiweMessage::parse(&message),
+                    Err(EthError::SiweFormatError(_))
+                        | Err(EthError::Eip55Error(_))
+                        | Err(EthError::AddressFormatError(_))
+                ),
+                "Expected parsing to fail:\n{}",
+                message
+            );
+        }
+    }
+
+    #[test]
+    fn siwe_parse_rejects_timestamp_overflow() {
+        let message = format_siwe_message(Some(STATEMENT), "")
+            .replace("2024-05-01T00:00:00Z", "9999-12-31T23:59:59Z");
+        assert!(matches!(
+            SiweMessage::parse(&message),
+            Err(EthError::SiweFormatError(e)) if e == "Timestamp out of range"
+        ));
+    }
+
+    #[test]
+    fn siwe_validate_valid_message() {
+        let siwe_message = valid_siwe_message();
+        assert!(siwe_message
+            .validate(DOMAIN, URI, 1, STATEMENT, NOW_NS)
+            .is_ok());
+    }
+
+    #[test]
+    fn siwe_validate_rejects_mismatches() {
+        let siwe_message = valid_siwe_message();
+        let cases = [
+            (
+                siwe_message.validate("other.com", URI, 1, STATEMENT, NOW_NS),
+                "Domain mismatch",
+            ),
+            (
+                siwe_message.validate(DOMAIN, "https://other.com", 1, STATEMENT, NOW_NS),
+                "URI mismatch",
+            ),
+            (
+                siwe_message.validate(DOMAIN, URI, 137, STATEMENT, NOW_NS),
+                "Chain ID mismatch",
+            ),
+            (
+                siwe_message.validate(DOMAIN, URI, 1, "Another statement", NOW_NS),
+                "Statement mismatch",
+            ),
+        ];
+
+        for (result, expected) in cases {
+            assert!(
+                matches!(&result, Err(EthError::SiweValidationError(e)) if e == expected),
+                "Expected {}, got {:?}",
+                expected,
+                result
+            );
+        }
+    }
+
+    #[test]
+    fn siwe_validate_rejects_invalid_times() {
+        let message = |optional_fields| {
+            SiweMessage::parse(&format_siwe_message(Some(STATEMENT), optional_fields)).unwrap()
+        };
+        let cases = [
+            (message(""), "Missing expiration time"),
+            (
+                message("\nExpiration Time: 2024-05-01T00:00:30Z"),
+                "Message expired",
+            ),
+            (
+                message("\nExpiration Time: 2024-05-01T01:00:00Z"),
+                "Expiration time too far in the future",
+            ),
+            (
+                message(
+                    "\nExpiration Time: 2024-05-01T00:05:00Z\nNot Before: 2024-05-01T00:02:00Z",
+                ),
+                "Message not yet valid",
+            ),
+        ];
+
+        for (siwe_message, expected) in cases {
+            let result = siwe_message.validate(DOMAIN, URI, 1, STATEMENT, NOW_NS);
+            assert!(
+                matches!(&result, Err(EthError::SiweValidationError(e)) if e == expected),
+                "Expected {}, got {:?}",
+                expected,
+                result
+            );
+        }
+    }
+
+    /// Signs the EIP-191 hash of messages until a signature with the requested y parity is found.
+    /// Returns the signer address, the message hash and `r || s`.
+    fn sign(y_parity: u8) -> (String, [u8; 32], [u8; 64]) {
+        let signing_key = SigningKey::from_slice(&[0x11; 32]).unwrap();
+        let address = derive_eth_address_from_public_key(signing_key.verifying_key()).unwrap();
+        for i in 0.. {
+            let message_hash = eip191_hash(&format!("message {}", i));
+            let (signature, recovery_id) =
+                signing_key.sign_prehash_recoverable(&message_hash).unwrap();
+            if recovery_id.is_y_odd() as u8 == y_parity {
+                return (address, message_hash, signature.to_bytes().into());
+            }
+        }
+        unreachable!()
+    }
+
+    fn signature_hex(r_s: &[u8; 64], v: &[u8]) -> String {
+        format!("0x{}{}", hex::encode(r_s), hex::encode(v))
+    }
+
+    #[test]
+    fn signature_recovery_values() {
+        let cases: [(&[u8], u8); 9] = [
+            (&[0], 0),
+            (&[1], 1),
+            (&[27], 0),
+            (&[28], 1),
+            // EIP-155, Ethereum mainnet
+            (&[37], 0),
+            (&[38], 1),
+            // EIP-155, Polygon
+            (&[0x01, 0x35], 0),
+            (&[0x01, 0x36], 1),
+            (&[0x00, 0x00, 0x25], 0),
+        ];
+
+        for (v, y_parity) in cases {
+            let (address, message_hash, r_s) = sign(y_parity);
+            let signature = EthSignature::new(&signature_hex(&r_s, v)).unwrap();
+            assert_eq!(signature.as_bytes()[64], 27 + y_parity, "v = {:?}", v);
+            assert_eq!(
+                recover_eth_address_from_hash(&message_hash, &signature).unwrap(),
+                address,
+                "v = {:?}",
+                v
+            );
+        }
+    }
+
+    #[test]
+    fn signature_rejects_invalid_recovery_values() {
+        let (_, _, r_s) = sign(0);
+        for v in [2, 26, 29, 34] {
+            assert!(matches!(
+                EthSignature::new(&signature_hex(&r_s, &[v])),
+                Err(EthError::InvalidRecoveryId)
+            ));
+        }
+    }
+
+    #[test]
+    fn signature_rejects_invalid_lengths() {
+        let (_, _, r_s) = sign(0);
+        for signature in [
+            format!("0x{}", hex::encode(&r_s[..63])),
+            signature_hex(&r_s, &[0; 33]),
+        ] {
+            assert!(matches!(
+                EthSignature::new(&signature),
+                Err(EthError::SignatureFormatError(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn signature_rejects_high_s() {
+        let (_, _, r_s) = sign(0);
+        let signature = Signature::from_slice(&r_s).unwrap();
+        let high_s =
+            Signature::from_scalars(signature.r().to_bytes(), (-*signature.s()).to_bytes())
+                .unwrap();
+        let high_s: [u8; 64] = high_s.to_bytes().into();
+
+        assert!(matches!(
+            EthSignature::new(&signature_hex(&high_s, &[27])),
+            Err(EthError::MalleableSignature)
+        ));
+    }
+
+    #[test]
+    fn signature_compact_matches_full() {
+        for y_parity in [0, 1] {
+            let (address, message_hash, r_s) = sign(y_parity);
+
+            let mut compact = r_s;
+            compact[32] |= y_parity << 7;
+            let compact = EthSignature::new(&format!("0x{}", hex::encode(compact))).unwrap();
+            let full = EthSignature::new(&signature_hex(&r_s, &[27 + y_parity])).unwrap();
+
+            assert_eq!(compact.as_bytes(), full.as_bytes());
+            assert_eq!(
+                recover_eth_address_from_hash(&message_hash, &compact).unwrap(),
+                address
+            );
+        }
+    }
+
+    fn link_identity(wallet: EthAddress) -> LinkIdentity {
+        LinkIdentity {
+            wallet,
+            principal: Principal::from_text("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap(),
+            nonce: "0123456789abcdef0123456789abcdef".to_string(),
+            expiry: 1_714_521_900,
+        }
+    }
+
+    // Expected values computed with an independent EIP-712 encoder.
+    #[test]
+    fn eip712_hash_known_answer() {
+        assert_eq!(
+            hex::encode(keccak256(LINK_IDENTITY_TYPE.as_bytes())),
+            "f0f0d9cdea611cefeb80f0240a731cc90f8c4fc2bdac2b495f424e184a75cef1"
+        );
+        assert_eq!(
+            hex::encode(eip712_domain_separator()),
+            "554d84171cfee8dc06a300a5304533ab26a1ef568e90f27018f15c24cc2b09d6"
+        );
+
+        let link = link_identity(EthAddress::new(ADDRESS).unwrap());
+        assert_eq!(
+            hex::encode(link.struct_hash().unwrap()),
+            "2e6b806d1f306304f26407d41b91fcdf545e3bcf78b4aa3fcceb75ac6511bdad"
+        );
+        assert_eq!(
+            hex::encode(link.eip712_hash().unwrap()),
+            "a53a8314570e538dbd89b4988b55d9d1b48b0f822cafe792e53e6d09b1f8c57c"
+        );
+    }
+
+    #[test]
+    fn eip712_hash_signature_recovers_wallet() {
+        let signing_key = SigningKey::from_slice(&[0x11; 32]).unwrap();
+        let address = derive_eth_address_from_public_key(signing_key.verifying_key()).unwrap();
+        let link = link_identity(EthAddress::new(&address).unwrap());
+
+        let hash = link.eip712_hash().unwrap();
+        let (signature, recovery_id) = signing_key.sign_prehash_recoverable(&hash).unwrap();
+        let r_s: [u8; 64] = signature.to_bytes().into();
+        let signature =
+            EthSignature::new(&signature_hex(&r_s, &[27 + recovery_id.is_y_odd() as u8])).unwrap();
+
+        assert_eq!(
+            recover_eth_address_from_hash(&hash, &signature).unwrap(),
+            address
+        );
+    }
 }
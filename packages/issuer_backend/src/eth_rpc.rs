@@ -0,0 +1,195 @@
+use crate::eth::EthAddress;
+use crate::http_transform::{EthRpcResponse, TransformSchema};
+use crate::outcall::{http_outcall, http_request_cost};
+use candid::Nat;
+use ic_cdk::api::management_canister::http_request::{
+    CanisterHttpRequestArgument, HttpHeader, HttpMethod,
+};
+use serde_json::json;
+
+/// Function selector of `isValidSignature(bytes32,bytes)`, also the magic value returned by the
+/// function when the signature is valid. See [EIP-1271 spec](https://eips.ethereum.org/EIPS/eip-1271).
+const EIP1271_MAGIC_VALUE: [u8; 4] = [0x16, 0x26, 0xba, 0x7e];
+
+/// JSON-RPC responses to `eth_call` are small, there is no need to pay for more.
+const ETH_RPC_MAX_RESPONSE_BYTES: u64 = 2_048;
+
+/// Encodes the calldata for `isValidSignature(bytes32 hash, bytes signature)`.
+fn encode_is_valid_signature(hash: &[u8; 32], signature: &[u8]) -> Vec<u8> {
+    let padded_len = signature.len().div_ceil(32) * 32;
+    let mut data = Vec::with_capacity(4 + 3 * 32 + padded_len);
+    data.extend_from_slice(&EIP1271_MAGIC_VALUE);
+    data.extend_from_slice(hash);
+
+    // Offset of the dynamic `bytes` argument, counted from the start of the arguments
+    let mut offset = [0u8; 32];
+    offset[31] = 0x40;
+    data.extend_from_slice(&offset);
+
+    let mut length = [0u8; 32];
+    length[24..].copy_from_slice(&(signature.len() as u64).to_be_bytes());
+    data.extend_from_slice(&length);
+
+    data.extend_from_slice(signature);
+    data.resize(4 + 3 * 32 + padded_len, 0);
+    data
+}
+
+/// Parses the JSON-RPC response to an `isValidSignature` call. The signature is valid only if the
+/// call returned the magic value.
+fn parse_is_valid_signature_response(body: &[u8]) -> Result<bool, String> {
+    // Parse the response body, already reduced to the result by the transform
+    let response: EthRpcResponse = serde_json::from_slice(body)
+        .map_err(|_| "Invalid JSON in Ethereum RPC response".to_string())?;
+
+    // A contract that does not implement EIP-1271, or an address that is not a contract,
+    // results in an error or an empty result. Neither means the signature is valid.
+    let result = match response.result {
+        Some(result) => result,
+        None => return Ok(false),
+    };
+
+    let result = hex::decode(result.trim_start_matches("0x"))
+        .map_err(|_| "Invalid result in Ethereum RPC response".to_string())?;
+
+    Ok(result.len() >= 4 && result[..4] == EIP1271_MAGIC_VALUE)
+}
+
+fn eip1271_request(
+    rpc_url: &str,
+    address: &EthAddress,
+    hash: &[u8; 32],
+    signature: &[u8],
+) -> CanisterHttpRequestArgument {
+    let body = json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": "eth_call",
+        "params": [
+            {
+                "to": address.as_str(),
+                "data": format!("0x{}", hex::encode(encode_is_valid_signature(hash, signature))),
+            },
+            "latest"
+        ]
+    });
+
+    CanisterHttpRequestArgument {
+        url: rpc_url.to_string(),
+        method: HttpMethod::POST,
+        body: Some(serde_json::to_vec(&body).unwrap()),
+        max_response_bytes: Some(ETH_RPC_MAX_RESPONSE_BYTES),
+        transform: Some(TransformSchema::EthRpc.context()),
+        headers: vec![HttpHeader {
+            name: "Content-Type".to_string(),
+            value: "application/json".to_string(),
+        }],
+    }
+}
+
+///
+/// The cycles it costs to check a signature using `is_valid_signature_eip1271`.
+///
+pub fn is_valid_signature_eip1271_cost(
+    rpc_url: &str,
+    address: &EthAddress,
+    hash: &[u8; 32],
+    signature: &[u8],
+) -> u128 {
+    http_request_cost(&eip1271_request(rpc_url, address, hash, signature))
+}
+
+///
+/// Check if a signature is valid for a smart contract wallet by calling `isValidSignature` on the
+/// contract through an Ethereum JSON-RPC endpoint, as specified by EIP-1271.
+///
+pub async fn is_valid_signature_eip1271(
+    rpc_url: &str,
+    address: &EthAddress,
+    hash: &[u8; 32],
+    signature: &[u8],
+) -> Result<bool, String> {
+    let request = eip1271_request(rpc_url, address, hash, signature);
+
+    match http_outcall(TransformSchema::EthRpc, request).await {
+        Ok((response,)) => {
+            if response.status != Nat::from(200u16) {
+                return Err(format!(
+                    "Ethereum RPC request failed with status {}",
+                    response.status
+                ));
+            }
+
+            parse_is_valid_signature_response(&response.body)
+        }
+        Err((_, m)) => Err(format!("Ethereum RPC request failed: {}", m)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_is_valid_signature_calldata() {
+        let hash = [0xaa; 32];
+        let signature = [0xbb; 65];
+
+        let expected = [
+            "1626ba7e",
+            "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa",
+            "0000000000000000000000000000000000000000000000000000000000000040",
+            "0000000000000000000000000000000000000000000000000000000000000041",
+            "bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb",
+            "bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb",
+            "bb00000000000000000000000000000000000000000000000000000000000000",
+        ]
+        .concat();
+
+        assert_eq!(
+            hex::encode(encode_is_valid_signature(&hash, &signature)),
+            expected
+        );
+    }
+
+    #[test]
+    fn encodes_word_aligned_signature_without_padding() {
+        let data = encode_is_valid_signature(&[0; 32], &[0xbb; 64]);
+        assert_eq!(data.len(), 4 + 3 * 32 + 64);
+        assert_eq!(data[4 + 3 * 32..], [0xbb; 64]);
+    }
+
+    #[test]
+    fn parses_is_valid_signature_responses() {
+        let cases: [(&str, Result<bool, &str>); 7] = [
+            (
+                r#"{"result":"0x1626ba7e00000000000000000000000000000000000000000000000000000000"}"#,
+                Ok(true),
+            ),
+            (
+                r#"{"result":"0xffffffff00000000000000000000000000000000000000000000000000000000"}"#,
+                Ok(false),
+            ),
+            (r#"{"result":"0x1626ba"}"#, Ok(false)),
+            (r#"{"result":"0x"}"#, Ok(false)),
+            (
+                r#"{"jsonrpc":"2.0","id":1,"error":{"code":-32000,"message":"execution reverted"}}"#,
+                Ok(false),
+            ),
+            (
+                r#"{"result":"0xnot hex"}"#,
+                Err("Invalid result in Ethereum RPC response"),
+            ),
+            ("not json", Err("Invalid JSON in Ethereum RPC response")),
+        ];
+
+        for (body, expected) in cases {
+            assert_eq!(
+                parse_is_valid_signature_response(body.as_bytes()),
+                expected.map_err(String::from),
+                "{}",
+                body
+            );
+        }
+    }
+}
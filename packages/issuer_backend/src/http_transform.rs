@@ -0,0 +1,242 @@
+use crate::passport_score_api::PassportScoreResponse;
+use candid::Nat;
+use ic_cdk::api::management_canister::http_request::{HttpResponse, TransformContext};
+use serde::{Deserialize, Serialize};
+
+/// The response schemas known to the `transform` query. The schema of an outcall is passed as the
+/// transform context, so that the transform knows which fields to keep.
+///
+/// All replicas must agree on the transformed response. Only the fields used by the canister are
+/// kept, serialized in a fixed order, and all headers are dropped. Anything else the server adds,
+/// such as timestamps or request ids, would otherwise prevent consensus.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransformSchema {
+    /// A score response from the Gitcoin Passport API, see `PassportScoreResponse`.
+    PassportScore,
+    /// A JSON-RPC response to an Ethereum `eth_call`, see `EthRpcResponse`.
+    EthRpc,
+}
+
+impl TransformSchema {
+    /// The name of the schema, used as transform context.
+    pub fn name(&self) -> &'static str {
+        match self {
+            TransformSchema::PassportScore => "passport_score",
+            TransformSchema::EthRpc => "eth_rpc",
+        }
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        match bytes {
+            b"passport_score" => Some(TransformSchema::PassportScore),
+            b"eth_rpc" => Some(TransformSchema::EthRpc),
+            _ => None,
+        }
+    }
+
+    /// The transform context to attach to an outcall expecting a response of this schema.
+    pub fn context(&self) -> TransformContext {
+        TransformContext::from_name("transform".to_string(), self.name().as_bytes().to_vec())
+    }
+}
+
+/// The only part of an Ethereum JSON-RPC response used by the canister. Error responses have no
+/// result.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EthRpcResponse {
+    pub result: Option<String>,
+}
+
+/// Body of responses with a non-200 status. Error bodies differ between servers and often contain
+/// request specific details, so they are replaced with a body only depending on the status.
+#[derive(Serialize)]
+struct ErrorBody {
+    error: String,
+}
+
+/// Parses a response body according to the schema and serializes it again with only the used
+/// fields. Returns `None` if the body does not match the schema.
+fn canonical_body(schema: TransformSchema, body: &[u8]) -> Option<Vec<u8>> {
+    match schema {
+        TransformSchema::PassportScore => {
+            let response: PassportScoreResponse = serde_json::from_slice(body).ok()?;
+            serde_json::to_vec(&response).ok()
+        }
+        TransformSchema::EthRpc => {
+            let response: EthRpcResponse = serde_json::from_slice(body).ok()?;
+            serde_json::to_vec(&response).ok()
+        }
+    }
+}
+
+/// Canonicalizes an outcall response using the schema selected by the transform context.
+///
+/// - Successful responses keep only the fields of the schema. A body that does not match the schema
+///   is replaced with an empty body, which callers reject as an invalid response.
+/// - Responses with any other status get a minimal error body only depending on the status.
+/// - Successful responses with an unknown context get an empty body, as there is no schema to
+///   canonicalize them with.
+///
+/// Headers are always dropped.
+pub fn canonicalize_response(response: HttpResponse, context: &[u8]) -> HttpResponse {
+    let status = response.status.clone();
+
+    if status != Nat::from(200u16) {
+        let body = ErrorBody {
+            error: format!("Request failed with status {}", status),
+        };
+        return HttpResponse {
+            status,
+            body: serde_json::to_vec(&body).unwrap_or_default(),
+            ..Default::default()
+        };
+    }
+
+    let body = TransformSchema::from_bytes(context)
+        .and_then(|schema| canonical_body(schema, &response.body))
+        .unwrap_or_default();
+
+    HttpResponse {
+        status,
+        body,
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ic_cdk::api::management_canister::http_request::HttpHeader;
+
+    fn response(status: u16, body: &str) -> HttpResponse {
+        HttpResponse {
+            status: Nat::from(status),
+            headers: vec![
+                HttpHeader {
+                    name: "date".to_string(),
+                    value: "Tue, 14 May 2024 10:31:07 GMT".to_string(),
+                },
+                HttpHeader {
+                    name: "cf-ray".to_string(),
+                    value: "8836c4a7ef1f2b6e-ARN".to_string(),
+                },
+            ],
+            body: body.as_bytes().to_vec(),
+        }
+    }
+
+    fn transform_passport_score(status: u16, body: &str) -> HttpResponse {
+        canonicalize_response(
+            response(status, body),
+            TransformSchema::PassportScore.name().as_bytes(),
+        )
+    }
+
+    fn assert_body(response: &HttpResponse, expected: &str) {
+        assert_eq!(String::from_utf8_lossy(&response.body), expected);
+        assert!(response.headers.is_empty());
+    }
+
+    #[test]
+    fn passport_score_done() {
+        let response = transform_passport_score(
+            200,
+            r#"{"address":"0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed","score":"23.510000000","status":"DONE","last_score_timestamp":"2024-05-14T10:31:07.051934+00:00","expiration_date":"2024-08-12T10:31:07.051934+00:00","evidence":null,"error":null,"stamp_scores":{"Google":2.25,"Discord":0.516}}"#,
+        );
+        assert_eq!(response.status, Nat::from(200u16));
+        assert_body(
+            &response,
+            r#"{"score":"23.510000000","status":"DONE","last_score_timestamp":"2024-05-14T10:31:07.051934+00:00","expiration_date":"2024-08-12T10:31:07.051934+00:00","evidence":null,"error":null}"#,
+        );
+    }
+
+    #[test]
+    fn passport_score_processing() {
+        let response = transform_passport_score(
+            200,
+            r#"{"address":"0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed","score":null,"status":"PROCESSING","last_score_timestamp":null,"expiration_date":null,"evidence":null,"error":null,"stamp_scores":{}}"#,
+        );
+        assert_body(
+            &response,
+            r#"{"score":null,"status":"PROCESSING","last_score_timestamp":null,"expiration_date":null,"evidence":null,"error":null}"#,
+        );
+    }
+
+    #[test]
+    fn passport_score_error() {
+        let response = transform_passport_score(
+            200,
+            r#"{"address":"0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed","score":null,"status":"ERROR","last_score_timestamp":null,"expiration_date":null,"evidence":null,"error":"No passport found for this address.","stamp_scores":{}}"#,
+        );
+        assert_body(
+            &response,
+            r#"{"score":null,"status":"ERROR","last_score_timestamp":null,"expiration_date":null,"evidence":null,"error":"No passport found for this address."}"#,
+        );
+    }
+
+    #[test]
+    fn passport_score_keeps_evidence() {
+        let response = transform_passport_score(
+            200,
+            r#"{"address":"0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed","score":"1.000000000","status":"DONE","last_score_timestamp":"2024-05-14T10:31:07.051934+00:00","expiration_date":null,"evidence":{"type":"ThresholdScoreCheck","success":true,"rawScore":"23.51","threshold":"20.00000"},"error":null,"stamp_scores":{}}"#,
+        );
+        assert_body(
+            &response,
+            r#"{"score":"1.000000000","status":"DONE","last_score_timestamp":"2024-05-14T10:31:07.051934+00:00","expiration_date":null,"evidence":{"rawScore":"23.51","success":true,"threshold":"20.00000","type":"ThresholdScoreCheck"},"error":null}"#,
+        );
+    }
+
+    #[test]
+    fn extra_fields_do_not_change_the_body() {
+        let body = r#"{"score":"1.0","status":"DONE","last_score_timestamp":null,"expiration_date":null,"evidence":null,"error":null}"#;
+        let with_extra_fields = r#"{"request_id":"a1b2c3","error":null,"evidence":null,"expiration_date":null,"last_score_timestamp":null,"status":"DONE","score":"1.0","served_at":"2024-05-14T10:31:07Z"}"#;
+
+        let first = transform_passport_score(200, body);
+        let second = transform_passport_score(200, with_extra_fields);
+        assert_eq!(first.body, second.body);
+        assert_body(&second, body);
+    }
+
+    #[test]
+    fn non_200_status_gets_minimal_error_body() {
+        let response = transform_passport_score(
+            429,
+            r#"{"detail":"Request was throttled. Expected available in 3 seconds.","request_id":"a1b2c3"}"#,
+        );
+        assert_eq!(response.status, Nat::from(429u16));
+        assert_body(&response, r#"{"error":"Request failed with status 429"}"#);
+    }
+
+    #[test]
+    fn body_not_matching_schema_is_emptied() {
+        let response = transform_passport_score(200, "<html>Bad gateway</html>");
+        assert_body(&response, "");
+    }
+
+    #[test]
+    fn unknown_context_empties_the_body() {
+        for context in [&b""[..], b"unknown"] {
+            let response = canonicalize_response(
+                response(200, r#"{"score":"1.0","served_at":"2024-05-14T10:31:07Z"}"#),
+                context,
+            );
+            assert_eq!(response.status, Nat::from(200u16));
+            assert_body(&response, "");
+        }
+    }
+
+    #[test]
+    fn eth_rpc_keeps_only_result() {
+        let response = canonicalize_response(
+            response(
+                200,
+                r#"{"jsonrpc":"2.0","id":1,"result":"0x1626ba7e00000000000000000000000000000000000000000000000000000000"}"#,
+            ),
+            TransformSchema::EthRpc.name().as_bytes(),
+        );
+        assert_body(
+            &response,
+            r#"{"result":"0x1626ba7e00000000000000000000000000000000000000000000000000000000"}"#,
+        );
+    }
+}
@@ -1,22 +1,44 @@
+mod address_hash;
 mod authenticated_guard;
 mod certified_data;
+mod controller_guard;
 mod eth;
+mod eth_rpc;
 mod http;
+mod http_transform;
+mod link;
+mod link_guard;
+mod nonce;
+mod outcall;
 mod passport_score_api;
+mod prune;
+mod random;
+mod rate_limit;
+mod score_provider;
+mod score_record;
+mod score_refresh;
 mod service;
 mod settings;
+mod storable_principal;
 mod vc;
 
 use asset_util::CertifiedAssets;
+use candid::Principal;
 use canister_sig_util::signature_map::SignatureMap;
 use http::{HttpRequest, HttpResponse};
 use ic_cdk::api::management_canister::http_request::TransformArgs;
 use ic_cdk::export_candid;
 use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
-use ic_stable_structures::{DefaultMemoryImpl, StableBTreeMap};
-use settings::Settings;
+use ic_stable_structures::{DefaultMemoryImpl, StableBTreeMap, StableCell};
+use link::{ScoreInfo, ScoreLinkError, UnlinkProof};
+use outcall::OutcallMetrics;
+use score_record::ScoreRecord;
 use settings::SettingsInput;
-use std::cell::RefCell;
+use settings::SettingsUpdate;
+use settings::{Settings, StoredSettings};
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeSet;
+use storable_principal::StorablePrincipal;
 use vc_util::issuer_api::{
     DerivationOriginData, DerivationOriginError, DerivationOriginRequest, GetCredentialRequest,
     Icrc21ConsentInfo, Icrc21Error, Icrc21VcConsentMessageRequest, IssueCredentialError,
@@ -26,31 +48,148 @@ use vc_util::issuer_api::{
 const CREDENTIAL_TYPE: &str = "GitcoinPassportScore";
 
 thread_local! {
+    // Memory ids 0 and 1 hold data keyed by principals truncated to 29 bytes, from before
+    // principals of any length were supported. That data is migrated on upgrade.
     static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> =
         RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
 
-    // SCORES maps principals to scores. The Ethereum address to which the principal is linked is
-    // not stored here
-    static PRINCIPAL_SCORE: RefCell<StableBTreeMap<[u8;29], f32, VirtualMemory<DefaultMemoryImpl>>> = RefCell::new(
+    /// Maps Ethereum addresses to principals. This is used to prevent multiple principals from
+    /// linking to the same Ethereum address. The ethereum address is the key and the principal is the value.
+    ///
+    /// The eth address is stored as a 32 byte keccak hash, keyed with `ADDRESS_PEPPER`, to preserve
+    /// the privacy of the user.
+    static ETH_PRINCIPAL: RefCell<StableBTreeMap<[u8;32], StorablePrincipal, VirtualMemory<DefaultMemoryImpl>>> = RefCell::new(
         StableBTreeMap::init(
-            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(0))),
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(8))),
         )
     );
 
+    /// Nonces issued to principals for linking an Ethereum address. A nonce is valid for a single
+    /// SIWE message. The key is the principal and the nonce, the value is the time at which the
+    /// nonce expires.
+    static LINK_NONCES: RefCell<StableBTreeMap<(StorablePrincipal, [u8;16]), u64, VirtualMemory<DefaultMemoryImpl>>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(2))),
+        )
+    );
 
-    /// Maps Ethereum addresses to principals. This is used to prevent multiple principals from
-    /// linking to the same Ethereum address. The ethereum address is the key and the principal is the value.
-    ///
-    /// The eth address is stored as a 32 keccak hash to preserve the privacy of the user.
-    ///
-    /// The principal is stored as a 29 byte slice to save space.
-    static ETH_PRINCIPAL: RefCell<StableBTreeMap<[u8;32], [u8;29], VirtualMemory<DefaultMemoryImpl>>> = RefCell::new(
+    /// Maps principals and the hashes of their linked Ethereum addresses to the score record of
+    /// each address. The principal comes first in the key, making it possible to iterate over all
+    /// addresses linked to a principal. The effective score of a principal is computed from these
+    /// records when read, see `link::effective_score`.
+    static PRINCIPAL_ADDRESSES: RefCell<StableBTreeMap<(StorablePrincipal, [u8;32]), ScoreRecord, VirtualMemory<DefaultMemoryImpl>>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(9))),
+        )
+    );
+
+    /// Maps principals to the hash of their primary Ethereum address. Used to compute the effective
+    /// score when the score policy is `Primary`.
+    static PRINCIPAL_PRIMARY: RefCell<StableBTreeMap<StorablePrincipal, [u8;32], VirtualMemory<DefaultMemoryImpl>>> = RefCell::new(
         StableBTreeMap::init(
-            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(1))),
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(4))),
         )
     );
 
+    /// Maps hashes of unlinked Ethereum addresses to the principal they were unlinked from and the
+    /// time of unlinking. Used to enforce a cooldown before the address can be linked to another
+    /// principal.
+    static UNLINK_COOLDOWNS: RefCell<StableBTreeMap<[u8;32], (StorablePrincipal, u64), VirtualMemory<DefaultMemoryImpl>>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(5))),
+        )
+    );
+
+    /// Maps the principal a transfer of linked addresses is offered to and the principal offering
+    /// the transfer, to the time at which the offer expires.
+    static PENDING_TRANSFERS: RefCell<StableBTreeMap<(StorablePrincipal, StorablePrincipal), u64, VirtualMemory<DefaultMemoryImpl>>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(6))),
+        )
+    );
+
+    /// Secret pepper used when hashing Ethereum addresses, generated once using `raw_rand`. All
+    /// zeroes until generated.
+    static ADDRESS_PEPPER: RefCell<StableCell<[u8;32], VirtualMemory<DefaultMemoryImpl>>> = RefCell::new(
+        StableCell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(13))),
+            [0; 32],
+        ).expect("Failed to initialize address pepper")
+    );
+
+    /// Cycles spent on HTTPS outcalls, keyed by the kind of outcall.
+    static OUTCALL_METRICS: RefCell<StableBTreeMap<String, OutcallMetrics, VirtualMemory<DefaultMemoryImpl>>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(14))),
+        )
+    );
+
+    /// Number of free outcalls used by each principal, see `outcall::charge_caller`.
+    static OUTCALL_QUOTAS: RefCell<StableBTreeMap<StorablePrincipal, u64, VirtualMemory<DefaultMemoryImpl>>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(15))),
+        )
+    );
+
+    /// Maps principals to the start of their current rate limiting window and the number of link
+    /// attempts made in that window, see `rate_limit::check_link_rate_limit`.
+    static PRINCIPAL_LINK_ATTEMPTS: RefCell<StableBTreeMap<StorablePrincipal, (u64, u32), VirtualMemory<DefaultMemoryImpl>>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(16))),
+        )
+    );
+
+    /// The token bucket shared by all principals for link attempts, holding the number of tokens
+    /// left and the time of the last refill.
+    static GLOBAL_LINK_BUCKET: RefCell<StableCell<(u64, u64), VirtualMemory<DefaultMemoryImpl>>> = RefCell::new(
+        StableCell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(17))),
+            (0, 0),
+        ).expect("Failed to initialize global link bucket")
+    );
+
+    /// Maps hashes of linked Ethereum addresses to the encrypted address and the time the score of
+    /// the address was last fetched or attempted to be refreshed. Used to refresh scores in the
+    /// background, see `score_refresh`.
+    static ADDRESS_REFRESH: RefCell<StableBTreeMap<[u8;32], ([u8;20], u64), VirtualMemory<DefaultMemoryImpl>>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(18))),
+        )
+    );
+
+    /// The entries of `ADDRESS_REFRESH` ordered by the time they were last refreshed, making it
+    /// possible to find stale scores without iterating over all addresses.
+    static ADDRESS_REFRESH_QUEUE: RefCell<StableBTreeMap<(u64, [u8;32]), (), VirtualMemory<DefaultMemoryImpl>>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(22))),
+        )
+    );
+
+    /// Random salt used to derive canister signature seeds, generated once using `raw_rand`. All
+    /// zeroes until generated, see `vc::credential_salt`.
+    static CREDENTIAL_SALT: RefCell<StableCell<[u8;32], VirtualMemory<DefaultMemoryImpl>>> = RefCell::new(
+        StableCell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(21))),
+            [0; 32],
+        ).expect("Failed to initialize credential salt")
+    );
+
+    /// The settings persisted across upgrades, see `settings::persist_settings`. The canister
+    /// reads the copy in `SETTINGS`.
+    static STORED_SETTINGS: RefCell<StableCell<StoredSettings, VirtualMemory<DefaultMemoryImpl>>> = RefCell::new(
+        StableCell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(19))),
+            StoredSettings::default(),
+        ).expect("Failed to initialize stored settings")
+    );
+
     static SETTINGS: RefCell<Option<Settings>> = const { RefCell::new(None) };
+    static SCORE_REFRESH_IN_PROGRESS: Cell<bool> = const { Cell::new(false) };
+
+    /// Principals and address hashes with a link in progress, see `link_guard::LinkGuard`. Kept on
+    /// the heap, no calls are in progress after an upgrade.
+    static LOCKED_PRINCIPALS: RefCell<BTreeSet<Principal>> = const { RefCell::new(BTreeSet::new()) };
+    static LOCKED_ADDRESSES: RefCell<BTreeSet<[u8; 32]>> = const { RefCell::new(BTreeSet::new()) };
     static SIGNATURES : RefCell<SignatureMap> = RefCell::new(SignatureMap::default());
     static ASSETS: RefCell<CertifiedAssets> = RefCell::new(CertifiedAssets::default());
 
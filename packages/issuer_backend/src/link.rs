@@ -0,0 +1,802 @@
+use crate::address_hash::{address_hash, encrypt_address};
+use crate::eth::{
+    eip191_hash, recover_eth_address_from_hash, EthAddress, EthError, EthSignature, SiweMessage,
+};
+use crate::eth_rpc::{is_valid_signature_eip1271, is_valid_signature_eip1271_cost};
+use crate::link_guard::{ensure_not_locked, LinkGuard};
+use crate::nonce::consume_nonce;
+use crate::outcall::{charge_caller, refund_outcalls};
+use crate::prune::prune_map;
+use crate::rate_limit::{check_link_rate_limit, RateLimitError};
+use crate::score_provider::{
+    fetch_score, fetch_score_cost, submit_score, submit_score_cost, ScoreProviderError, ScoreResult,
+};
+use crate::score_record::ScoreRecord;
+use crate::score_refresh::{cancel_refresh, schedule_refresh};
+use crate::settings::ScorePolicy;
+use crate::storable_principal::StorablePrincipal;
+use crate::{
+    ETH_PRINCIPAL, PENDING_TRANSFERS, PRINCIPAL_ADDRESSES, PRINCIPAL_PRIMARY, SETTINGS,
+    UNLINK_COOLDOWNS,
+};
+use candid::{CandidType, Principal};
+use ic_cdk::{api::time, caller, spawn};
+use ic_cdk_timers::set_timer;
+use serde::Deserialize;
+use std::time::Duration;
+
+/// How often expired transfer offers are removed.
+const LINK_TRANSFER_PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60); // 1 hour
+
+/// Maximum number of transfer offers inspected per message when pruning.
+const LINK_TRANSFER_PRUNE_BATCH_SIZE: usize = 500;
+
+/// Time between polls of the Passport API while a score is being processed.
+const PASSPORT_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Maximum number of times the Passport API is polled for a score that is being processed.
+const PASSPORT_POLL_MAX_ATTEMPTS: u32 = 6;
+
+/// Errors returned when linking an Ethereum address and its passport score.
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub enum ScoreLinkError {
+    /// The score could not be fetched from the score provider. If the score is still being
+    /// processed, the canister keeps polling the score provider in the background and the address
+    /// is linked once the score is available.
+    ScoreProvider(ScoreProviderError),
+    /// Too many link attempts, by the caller or by all principals together.
+    RateLimited(RateLimitError),
+    /// Any other validation or linking error.
+    Link(String),
+}
+
+impl From<String> for ScoreLinkError {
+    fn from(error: String) -> Self {
+        ScoreLinkError::Link(error)
+    }
+}
+
+impl From<EthError> for ScoreLinkError {
+    fn from(error: EthError) -> Self {
+        ScoreLinkError::Link(error.to_string())
+    }
+}
+
+impl From<RateLimitError> for ScoreLinkError {
+    fn from(error: RateLimitError) -> Self {
+        ScoreLinkError::RateLimited(error)
+    }
+}
+
+impl From<ScoreProviderError> for ScoreLinkError {
+    fn from(error: ScoreProviderError) -> Self {
+        ScoreLinkError::ScoreProvider(error)
+    }
+}
+
+/// Verifies that the signature over the message hash was made by the address.
+///
+/// Signatures are first checked with ecrecover, which covers regular externally owned accounts. If
+/// that fails and an Ethereum RPC URL is configured, the address is assumed to be a smart contract
+/// wallet and the signature is checked using EIP-1271 `isValidSignature`. The caller is charged
+/// for that outcall, and refunded if it fails, see `charge_caller`.
+///
+/// # Arguments
+///
+/// * `address` - The address that is expected to have made the signature.
+/// * `message_hash` - The hash of the signed message.
+/// * `signature` - The hex-encoded signature.
+pub async fn verify_signature(
+    address: &EthAddress,
+    message_hash: &[u8; 32],
+    signature: &str,
+) -> Result<(), String> {
+    let ecrecover_error: String = match EthSignature::new(signature) {
+        Ok(signature) => match recover_eth_address_from_hash(message_hash, &signature) {
+            Ok(recovered_address) if recovered_address == address.as_str() => return Ok(()),
+            Ok(_) => "Invalid signature".to_string(),
+            Err(e) => e.into(),
+        },
+        Err(e) => e.into(),
+    };
+
+    let rpc_url = SETTINGS.with_borrow(|settings_opt| {
+        settings_opt
+            .as_ref()
+            .expect("Settings should be initialized")
+            .eth_rpc_url
+            .clone()
+    });
+    let Some(rpc_url) = rpc_url else {
+        return Err(ecrecover_error);
+    };
+
+    let signature_bytes = signature
+        .strip_prefix("0x")
+        .and_then(|s| hex::decode(s).ok())
+        .ok_or(ecrecover_error)?;
+
+    let cost = is_valid_signature_eip1271_cost(&rpc_url, address, message_hash, &signature_bytes);
+    let charge = charge_caller(&StorablePrincipal(caller()), 1, cost)?;
+
+    match is_valid_signature_eip1271(&rpc_url, address, message_hash, &signature_bytes).await {
+        Ok(true) => {
+            charge.collect();
+            Ok(())
+        }
+        Ok(false) => {
+            charge.collect();
+            Err("Invalid signature".to_string())
+        }
+        Err(e) => {
+            charge.refund();
+            Err(e)
+        }
+    }
+}
+
+/// Verifies a signed SIWE message, see `SiweMessage::validate` and `verify_signature` for the
+/// checks performed. The nonce in the message is consumed before the signature is verified, making
+/// sure the signature cannot be used again and that no outcall is made for an unknown or expired
+/// nonce. A failed verification requires a new nonce.
+///
+/// # Arguments
+///
+/// * `message` - The SIWE message signed by the Ethereum address.
+/// * `signature` - The hex-encoded signature.
+/// * `statement` - The statement the caller is expected to have signed.
+///
+/// # Returns
+///
+/// * `Ok(SiweMessage)` - The parsed message.
+/// * `Err(String)` - An error message if any validation fails.
+pub async fn verify_siwe_message(
+    message: &str,
+    signature: &str,
+    statement: &str,
+) -> Result<SiweMessage, String> {
+    // Parse the SIWE message and validate it against the settings of this canister.
+    let siwe_message = SiweMessage::parse(message)?;
+    SETTINGS.with_borrow(|settings_opt| {
+        let settings = settings_opt
+            .as_ref()
+            .expect("Settings should be initialized");
+
+        siwe_message.validate(
+            &settings.siwe_domain,
+            &settings.siwe_uri,
+            settings.siwe_chain_id,
+            statement,
+            time(),
+        )
+    })?;
+
+    // Make sure the signature cannot be used again, also by calls made while it is verified.
+    consume_nonce(&caller(), &siwe_message.nonce)?;
+
+    // Verify that the message was signed by the address in the message.
+    verify_signature(&siwe_message.address, &eip191_hash(message), signature).await?;
+
+    Ok(siwe_message)
+}
+
+/// The score of a principal together with the number of addresses linked to it.
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct ScoreInfo {
+    pub score: f32,
+    pub linked_addresses: u32,
+    /// When the score was fetched, in nanoseconds since the Unix epoch. Zero if unknown.
+    pub fetched_at: u64,
+    /// When the score expires according to the score provider, in nanoseconds since the Unix epoch.
+    pub expires_at: Option<u64>,
+}
+
+/// A signed SIWE message proving control over the address to unlink.
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct UnlinkProof {
+    pub message: String,
+    pub signature: String,
+}
+
+/// Computes the effective score of a principal from the scores of its linked addresses, according
+/// to the configured score policy. The effective score is not stored, so it always reflects the
+/// current score policy and address scores.
+///
+/// Returns the score record of the address determining the effective score, or `None` if the
+/// principal has no linked addresses.
+pub fn effective_score(principal: &StorablePrincipal) -> Option<ScoreRecord> {
+    effective_score_of(principal, |_| true)
+}
+
+/// Computes the effective score of a principal like `effective_score`, only considering the score
+/// records accepted by `eligible`. Under the `Max` policy, the highest eligible score is used.
+pub fn effective_score_of(
+    principal: &StorablePrincipal,
+    eligible: impl Fn(&ScoreRecord) -> bool,
+) -> Option<ScoreRecord> {
+    let policy = SETTINGS.with_borrow(|settings_opt| {
+        settings_opt
+            .as_ref()
+            .expect("Settings should be initialized")
+            .score_policy
+    });
+
+    match policy {
+        ScorePolicy::Max => PRINCIPAL_ADDRESSES.with_borrow(|addresses| {
+            addresses
+                .range((*principal, [0; 32])..=(*principal, [u8::MAX; 32]))
+                .map(|(_, record)| record)
+                .filter(|record| eligible(record))
+                .reduce(|max, record| {
+                    if record.score > max.score {
+                        record
+                    } else {
+                        max
+                    }
+                })
+        }),
+        ScorePolicy::Primary => {
+            let primary = PRINCIPAL_PRIMARY.with_borrow(|p| p.get(principal))?;
+            PRINCIPAL_ADDRESSES
+                .with_borrow(|addresses| addresses.get(&(*principal, primary)))
+                .filter(|record| eligible(record))
+        }
+    }
+}
+
+/// Returns the number of addresses linked to a principal.
+pub fn linked_address_count(principal: &StorablePrincipal) -> u32 {
+    PRINCIPAL_ADDRESSES.with_borrow(|addresses| {
+        addresses
+            .range((*principal, [0; 32])..=(*principal, [u8::MAX; 32]))
+            .count() as u32
+    })
+}
+
+/// Submits an Ethereum address to the configured score provider and links the address to the
+/// principal once the score is available.
+///
+/// Callers that have used their free quota of outcalls must attach cycles to pay for the request
+/// to the score provider, see `charge_caller`. The payment is refunded if the request fails.
+///
+/// The principal and address are locked for the duration of the call, see `LinkGuard`. All checks
+/// are made before the score is requested, and made again once the score is available.
+///
+/// If the provider is still processing the score, it is polled in the background using timers, at
+/// most `PASSPORT_POLL_MAX_ATTEMPTS` times, and `ScoreProviderError::Processing` is returned. The
+/// address is linked as soon as a poll returns a final score. All polls count as link attempts of
+/// the principal and are paid for by this call. Polls that fail or are not made are refunded as
+/// free outcalls, see `outcall::refund_outcalls`.
+///
+/// # Arguments
+///
+/// * `principal` - The principal to link the address to.
+/// * `address` - The Ethereum address to be registered or refreshed.
+///
+/// # Returns
+///
+/// * `Ok(f32)` - The passport score of the address if registration or refresh is successful.
+/// * `Err(ScoreLinkError)` - An error if fetching the score or any validation or operation fails.
+pub async fn link_address(
+    principal: &Principal,
+    address: &EthAddress,
+) -> Result<f32, ScoreLinkError> {
+    let address_hash = address_hash(address)?;
+
+    // Reserve the principal and address until the score has been registered. The guard is released
+    // when dropped, also if the call traps after the score has been requested.
+    let _guard = reserve_link(principal, &address_hash, &LinkContext::current())?;
+
+    // Make the caller pay for the request to the score provider, if configured.
+    let charge = charge_caller(
+        &StorablePrincipal(*principal),
+        1,
+        submit_score_cost(address),
+    )?;
+
+    match submit_score(address).await {
+        Ok(result) => {
+            charge.collect();
+            Ok(register_score(principal, address, &result)?)
+        }
+        Err(ScoreProviderError::Processing) => {
+            charge.collect();
+
+            // The polls are made on behalf of this call, count them against its rate limits and
+            // make the caller pay for all of them up front.
+            check_link_rate_limit(principal, PASSPORT_POLL_MAX_ATTEMPTS)?;
+            let poll_charge = charge_caller(
+                &StorablePrincipal(*principal),
+                PASSPORT_POLL_MAX_ATTEMPTS.into(),
+                u128::from(PASSPORT_POLL_MAX_ATTEMPTS) * fetch_score_cost(address),
+            )?;
+            let polls_paid = poll_charge.is_paid();
+            poll_charge.collect();
+
+            schedule_score_poll(*principal, address.clone(), polls_paid);
+            Err(ScoreProviderError::Processing.into())
+        }
+        Err(e @ ScoreProviderError::RequestFailed(_)) => {
+            charge.refund();
+            Err(e.into())
+        }
+        Err(e) => {
+            charge.collect();
+            Err(e.into())
+        }
+    }
+}
+
+/// Polls the score provider for the score of an address that is being processed, registering the
+/// score once it is available. Polls that fail or are still processing, or that find the principal
+/// or address locked by another link, are retried until `PASSPORT_POLL_MAX_ATTEMPTS` is reached.
+///
+/// If `polls_paid` is set, the caller of `link_address` paid for all polls up front. Once polling
+/// ends, the polls that failed or were not made are refunded.
+fn schedule_score_poll(principal: Principal, address: EthAddress, polls_paid: bool) {
+    schedule_score_poll_attempt(principal, address, 1, 0, polls_paid);
+}
+
+fn schedule_score_poll_attempt(
+    principal: Principal,
+    address: EthAddress,
+    attempt: u32,
+    polls_made: u32,
+    polls_paid: bool,
+) {
+    set_timer(PASSPORT_POLL_INTERVAL, move || {
+        spawn(async move {
+            let refund_unused_polls = |polls_made: u32| {
+                if polls_paid {
+                    refund_outcalls(
+                        &StorablePrincipal(principal),
+                        (PASSPORT_POLL_MAX_ATTEMPTS - polls_made).into(),
+                    );
+                }
+            };
+
+            let guard = address_hash(&address)
+                .and_then(|address_hash| LinkGuard::acquire(&principal, &address_hash));
+            let _guard = match guard {
+                Ok(guard) => guard,
+                Err(_) if attempt < PASSPORT_POLL_MAX_ATTEMPTS => {
+                    schedule_score_poll_attempt(
+                        principal,
+                        address,
+                        attempt + 1,
+                        polls_made,
+                        polls_paid,
+                    );
+                    return;
+                }
+                Err(e) => {
+                    refund_unused_polls(polls_made);
+                    ic_cdk::api::print(format!("Failed to poll passport score: {}", e));
+                    return;
+                }
+            };
+
+            let result = fetch_score(&address).await;
+            // Failed requests are not counted, they are refunded.
+            let polls_made = match result {
+                Err(ScoreProviderError::RequestFailed(_)) => polls_made,
+                _ => polls_made + 1,
+            };
+
+            match result {
+                Ok(result) => {
+                    refund_unused_polls(polls_made);
+                    if let Err(e) = register_score(&principal, &address, &result) {
+                        ic_cdk::api::print(format!(
+                            "Failed to register polled passport score: {}",
+                            e
+                        ));
+                    }
+                }
+                Err(ScoreProviderError::Processing | ScoreProviderError::RequestFailed(_))
+                    if attempt < PASSPORT_POLL_MAX_ATTEMPTS =>
+                {
+                    schedule_score_poll_attempt(
+                        principal,
+                        address,
+                        attempt + 1,
+                        polls_made,
+                        polls_paid,
+                    );
+                }
+                Err(e) => {
+                    refund_unused_polls(polls_made);
+                    ic_cdk::api::print(format!("Failed to poll passport score: {}", e));
+                }
+            }
+        });
+    });
+}
+
+/// The limits a link is checked against, together with the time of the check, see
+/// `check_link_allowed`.
+struct LinkContext {
+    now: u64,
+    max_linked_addresses: u32,
+    unlink_cooldown_ns: u64,
+}
+
+impl LinkContext {
+    /// The context from the current settings and time.
+    fn current() -> Self {
+        SETTINGS.with_borrow(|settings_opt| {
+            let settings = settings_opt
+                .as_ref()
+                .expect("Settings should be initialized");
+            LinkContext {
+                now: time(),
+                max_linked_addresses: settings.max_linked_addresses,
+                unlink_cooldown_ns: settings.unlink_cooldown_ns,
+            }
+        })
+    }
+}
+
+/// Reserves the principal and address for a link, see `LinkGuard`, and makes sure the link is
+/// allowed before the score is requested. The reservation lasts until the guard is dropped.
+fn reserve_link(
+    principal: &Principal,
+    address_hash: &[u8; 32],
+    context: &LinkContext,
+) -> Result<LinkGuard, String> {
+    let guard = LinkGuard::acquire(principal, address_hash)?;
+    check_link_allowed(&StorablePrincipal(*principal), address_hash, context)?;
+    Ok(guard)
+}
+
+/// Links the address to the principal, unless already linked, and stores the score record of the
+/// address. Links can change while the score is requested, so the link is checked again first.
+fn commit_link(
+    principal: &StorablePrincipal,
+    address_hash: &[u8; 32],
+    record: ScoreRecord,
+    context: &LinkContext,
+) -> Result<(), String> {
+    if !check_link_allowed(principal, address_hash, context)? {
+        // Register the new address
+        ETH_PRINCIPAL.with_borrow_mut(|e| {
+            e.insert(*address_hash, *principal);
+        });
+
+        PRINCIPAL_PRIMARY.with_borrow_mut(|p| {
+            if !p.contains_key(principal) {
+                p.insert(*principal, *address_hash);
+            }
+        });
+    }
+
+    // Register or update the score of the address.
+    PRINCIPAL_ADDRESSES.with_borrow_mut(|a| {
+        a.insert((*principal, *address_hash), record);
+    });
+
+    Ok(())
+}
+
+/// Makes sure the address can be linked to the principal: the address is not linked to another
+/// principal, has not recently been unlinked from another principal, and the principal stays within
+/// the maximum number of linked addresses.
+///
+/// Returns whether the address is already linked to the principal.
+fn check_link_allowed(
+    principal: &StorablePrincipal,
+    address_hash: &[u8; 32],
+    context: &LinkContext,
+) -> Result<bool, String> {
+    // Ensure the address is not linked to another principal.
+    let eth_already_registered = ETH_PRINCIPAL.with_borrow(|map| match map.get(address_hash) {
+        Some(linked_principal) if linked_principal != *principal => {
+            Err("Invalid eth/principal combination".to_string())
+        }
+        Some(_) => Ok(true),
+        None => Ok(false),
+    })?;
+
+    if !eth_already_registered {
+        // Ensure the address has not recently been unlinked from another principal.
+        check_unlink_cooldown(principal, address_hash, context)?;
+
+        // Ensure the principal stays within the maximum number of linked addresses.
+        if linked_address_count(principal) >= context.max_linked_addresses {
+            return Err("Maximum number of linked addresses reached".to_string());
+        }
+    }
+
+    Ok(eth_already_registered)
+}
+
+/// Links an Ethereum address to the principal and registers the passport score of the address.
+///
+/// The principal must already have proven control over the address, this function only makes sure
+/// that the link is allowed, see `check_link_allowed`. The first address linked to a principal
+/// becomes its primary address.
+///
+/// The address is stored encrypted, so that the score can be refreshed in the background, see
+/// `score_refresh`.
+///
+/// # Arguments
+///
+/// * `principal` - The principal to link the address to.
+/// * `address` - The Ethereum address to be registered or refreshed.
+/// * `result` - The score of the address, as returned by the score provider.
+pub fn register_score(
+    principal: &Principal,
+    address: &EthAddress,
+    result: &ScoreResult,
+) -> Result<f32, String> {
+    let address_hash = address_hash(address)?;
+    let context = LinkContext::current();
+
+    commit_link(
+        &StorablePrincipal(*principal),
+        &address_hash,
+        ScoreRecord::new(result, address_hash, context.now),
+        &context,
+    )?;
+
+    let encrypted_address = encrypt_address(address, &address_hash)?;
+    schedule_refresh(address_hash, encrypted_address, context.now);
+
+    Ok(result.score)
+}
+
+/// Makes sure an address that was recently unlinked from or transferred by one principal is not
+/// linked or transferred to another principal before the configured cooldown has passed. Expired
+/// cooldowns are removed.
+fn check_unlink_cooldown(
+    principal: &StorablePrincipal,
+    address_hash: &[u8; 32],
+    context: &LinkContext,
+) -> Result<(), String> {
+    UNLINK_COOLDOWNS.with_borrow_mut(|cooldowns| {
+        let Some((unlinked_principal, unlinked_at)) = cooldowns.get(address_hash) else {
+            return Ok(());
+        };
+        if unlinked_at.saturating_add(context.unlink_cooldown_ns) <= context.now {
+            cooldowns.remove(address_hash);
+            return Ok(());
+        }
+        if unlinked_principal != *principal {
+            return Err(
+                "Address was recently unlinked or transferred, try again later".to_string(),
+            );
+        }
+        Ok(())
+    })
+}
+
+/// Unlinks an Ethereum address from a principal, erasing the score of the address. If it was the
+/// last address linked to the principal, the score of the principal is erased as well.
+///
+/// If an unlink cooldown is configured, the address cannot be linked to another principal until
+/// the cooldown has passed. Fails while a link for the principal is in progress.
+///
+/// # Arguments
+///
+/// * `principal` - The principal to unlink the address from.
+/// * `address_hash` - The hash of the Ethereum address to unlink.
+pub fn unlink_address(
+    principal: &StorablePrincipal,
+    address_hash: &[u8; 32],
+) -> Result<(), String> {
+    ensure_not_locked(&principal.0)?;
+
+    ETH_PRINCIPAL.with_borrow_mut(|e| match e.get(address_hash) {
+        Some(linked_principal) if linked_principal == *principal => {
+            e.remove(address_hash);
+            Ok(())
+        }
+        _ => Err("Address not linked to principal".to_string()),
+    })?;
+
+    PRINCIPAL_ADDRESSES.with_borrow_mut(|a| {
+        a.remove(&(*principal, *address_hash));
+    });
+    cancel_refresh(address_hash);
+
+    // If the primary address was unlinked, the next linked address becomes primary.
+    let next_address = PRINCIPAL_ADDRESSES.with_borrow(|a| {
+        a.range((*principal, [0; 32])..=(*principal, [u8::MAX; 32]))
+            .next()
+            .map(|((_, address_hash), _)| address_hash)
+    });
+    PRINCIPAL_PRIMARY.with_borrow_mut(|p| {
+        if p.get(principal) == Some(*address_hash) {
+            match next_address {
+                Some(next_address) => p.insert(*principal, next_address),
+                None => p.remove(principal),
+            };
+        }
+    });
+
+    let cooldown = SETTINGS.with_borrow(|settings_opt| {
+        settings_opt
+            .as_ref()
+            .expect("Settings should be initialized")
+            .unlink_cooldown_ns
+    });
+    if cooldown > 0 {
+        UNLINK_COOLDOWNS.with_borrow_mut(|c| {
+            c.insert(*address_hash, (*principal, time()));
+        });
+    }
+
+    Ok(())
+}
+
+/// Moves all linked addresses, their scores and the primary address from one principal to another.
+/// The principal receiving the links must not have any linked addresses.
+///
+/// Transfers are subject to the same cooldown as unlinking: addresses that were recently unlinked
+/// or transferred cannot be transferred onwards until the cooldown has passed, see
+/// `check_unlink_cooldown`.
+///
+/// All checks are made before any data is changed and no calls are made, so the transfer either
+/// happens completely or not at all. Fails while a link for either principal is in progress.
+///
+/// # Arguments
+///
+/// * `from` - The principal the addresses are currently linked to.
+/// * `to` - The principal to link the addresses to.
+pub fn transfer_links(from: &StorablePrincipal, to: &StorablePrincipal) -> Result<(), String> {
+    ensure_not_locked(&from.0)?;
+    ensure_not_locked(&to.0)?;
+
+    if linked_address_count(to) > 0 {
+        return Err("Principal already has linked addresses".to_string());
+    }
+
+    let addresses: Vec<([u8; 32], ScoreRecord)> = PRINCIPAL_ADDRESSES.with_borrow(|a| {
+        a.range((*from, [0; 32])..=(*from, [u8::MAX; 32]))
+            .map(|((_, address_hash), record)| (address_hash, record))
+            .collect()
+    });
+    if addresses.is_empty() {
+        return Err("No linked addresses to transfer".to_string());
+    }
+    let context = LinkContext::current();
+    for (address_hash, _) in addresses.iter() {
+        check_unlink_cooldown(to, address_hash, &context)?;
+    }
+
+    for (address_hash, record) in addresses {
+        PRINCIPAL_ADDRESSES.with_borrow_mut(|a| {
+            a.remove(&(*from, address_hash));
+            a.insert((*to, address_hash), record);
+        });
+        ETH_PRINCIPAL.with_borrow_mut(|e| {
+            e.insert(address_hash, *to);
+        });
+        if context.unlink_cooldown_ns > 0 {
+            UNLINK_COOLDOWNS.with_borrow_mut(|c| {
+                c.insert(address_hash, (*from, context.now));
+            });
+        }
+    }
+
+    if let Some(primary) = PRINCIPAL_PRIMARY.with_borrow_mut(|p| p.remove(from)) {
+        PRINCIPAL_PRIMARY.with_borrow_mut(|p| p.insert(*to, primary));
+    }
+
+    PENDING_TRANSFERS.with_borrow_mut(|t| t.remove(&(*to, *from)));
+
+    Ok(())
+}
+
+/// Starts the timer that periodically prunes expired transfer offers.
+pub fn init_transfer_pruning() {
+    ic_cdk_timers::set_timer_interval(LINK_TRANSFER_PRUNE_INTERVAL, || {
+        let now = time();
+        prune_map(
+            &PENDING_TRANSFERS,
+            move |_, expires_at| *expires_at <= now,
+            LINK_TRANSFER_PRUNE_BATCH_SIZE,
+        );
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u64 = 1_714_521_600 * 1_000_000_000;
+
+    fn link_context(max_linked_addresses: u32) -> LinkContext {
+        LinkContext {
+            now: NOW,
+            max_linked_addresses,
+            unlink_cooldown_ns: 0,
+        }
+    }
+
+    fn commit(principal: &Principal, address_hash: &[u8; 32], context: &LinkContext) {
+        commit_link(
+            &StorablePrincipal(*principal),
+            address_hash,
+            ScoreRecord::legacy(1.0, *address_hash),
+            context,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn second_link_is_rejected_while_first_is_in_flight() {
+        let a = Principal::from_slice(&[1; 29]);
+        let b = Principal::from_slice(&[2; 29]);
+        let x = [1; 32];
+        let y = [2; 32];
+        let context = link_context(2);
+
+        let guard = reserve_link(&a, &x, &context).unwrap();
+        assert_eq!(
+            reserve_link(&b, &x, &context).err().unwrap(),
+            "A link for this address is already in progress"
+        );
+        assert_eq!(
+            reserve_link(&a, &y, &context).err().unwrap(),
+            "A link for this principal is already in progress"
+        );
+
+        commit(&a, &x, &context);
+        drop(guard);
+
+        assert_eq!(
+            reserve_link(&b, &x, &context).err().unwrap(),
+            "Invalid eth/principal combination"
+        );
+        assert!(reserve_link(&a, &y, &context).is_ok());
+    }
+
+    #[test]
+    fn commit_checks_again_that_the_link_is_allowed() {
+        let a = Principal::from_slice(&[1; 29]);
+        let b = Principal::from_slice(&[2; 29]);
+        let x = [1; 32];
+        let y = [2; 32];
+        let context = link_context(1);
+
+        // The address is linked to another principal while the score is requested.
+        let guard = reserve_link(&a, &x, &context).unwrap();
+        commit(&b, &x, &context);
+        assert_eq!(
+            commit_link(
+                &StorablePrincipal(a),
+                &x,
+                ScoreRecord::legacy(1.0, x),
+                &context
+            )
+            .err()
+            .unwrap(),
+            "Invalid eth/principal combination"
+        );
+        drop(guard);
+
+        // The principal reaches the maximum number of addresses while the score is requested.
+        let guard = reserve_link(&b, &y, &link_context(2)).unwrap();
+        assert_eq!(
+            commit_link(
+                &StorablePrincipal(b),
+                &y,
+                ScoreRecord::legacy(1.0, y),
+                &context
+            )
+            .err()
+            .unwrap(),
+            "Maximum number of linked addresses reached"
+        );
+        drop(guard);
+
+        assert_eq!(
+            ETH_PRINCIPAL.with_borrow(|e| e.get(&x)),
+            Some(StorablePrincipal(b))
+        );
+        assert_eq!(ETH_PRINCIPAL.with_borrow(|e| e.get(&y)), None);
+        assert_eq!(linked_address_count(&StorablePrincipal(a)), 0);
+        assert_eq!(linked_address_count(&StorablePrincipal(b)), 1);
+    }
+}
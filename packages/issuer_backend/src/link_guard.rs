@@ -0,0 +1,78 @@
+use crate::{LOCKED_ADDRESSES, LOCKED_PRINCIPALS};
+use candid::Principal;
+
+/// Locks a principal and the hash of an Ethereum address while a link is in progress.
+///
+/// Linking awaits the score provider, during which other calls can be executed. Holding the guard
+/// prevents concurrent calls from linking the same address to different principals, or linking more
+/// addresses to one principal than allowed, based on checks made before the await.
+///
+/// The locks are released when the guard is dropped. If a call traps after an await, the CDK drops
+/// the state of the call during cleanup, releasing the locks as well.
+pub struct LinkGuard {
+    principal: Principal,
+    address_hash: [u8; 32],
+}
+
+impl LinkGuard {
+    pub fn acquire(principal: &Principal, address_hash: &[u8; 32]) -> Result<Self, String> {
+        ensure_not_locked(principal)?;
+        if LOCKED_ADDRESSES.with_borrow(|a| a.contains(address_hash)) {
+            return Err("A link for this address is already in progress".to_string());
+        }
+
+        LOCKED_PRINCIPALS.with_borrow_mut(|p| p.insert(*principal));
+        LOCKED_ADDRESSES.with_borrow_mut(|a| a.insert(*address_hash));
+
+        Ok(Self {
+            principal: *principal,
+            address_hash: *address_hash,
+        })
+    }
+}
+
+impl Drop for LinkGuard {
+    fn drop(&mut self) {
+        LOCKED_PRINCIPALS.with_borrow_mut(|p| p.remove(&self.principal));
+        LOCKED_ADDRESSES.with_borrow_mut(|a| a.remove(&self.address_hash));
+    }
+}
+
+/// Fails if a link for the principal is in progress. Used by functions changing the links of a
+/// principal, that would otherwise interfere with the link in progress.
+pub fn ensure_not_locked(principal: &Principal) -> Result<(), String> {
+    if LOCKED_PRINCIPALS.with_borrow(|p| p.contains(principal)) {
+        return Err("A link for this principal is already in progress".to_string());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn locks_principal_and_address_until_dropped() {
+        let a = Principal::from_slice(&[1; 29]);
+        let b = Principal::from_slice(&[2; 29]);
+        let x = [1; 32];
+        let y = [2; 32];
+
+        let guard = LinkGuard::acquire(&a, &x).unwrap();
+        assert_eq!(
+            LinkGuard::acquire(&b, &x).err().unwrap(),
+            "A link for this address is already in progress"
+        );
+        assert_eq!(
+            LinkGuard::acquire(&a, &y).err().unwrap(),
+            "A link for this principal is already in progress"
+        );
+        assert!(ensure_not_locked(&a).is_err());
+        assert!(ensure_not_locked(&b).is_ok());
+
+        drop(guard);
+        assert!(ensure_not_locked(&a).is_ok());
+        assert!(LinkGuard::acquire(&b, &x).is_ok());
+        assert!(LinkGuard::acquire(&a, &y).is_ok());
+    }
+}
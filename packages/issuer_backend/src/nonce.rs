@@ -0,0 +1,86 @@
+use crate::prune::prune_map;
+use crate::{storable_principal::StorablePrincipal, LINK_NONCES};
+use candid::Principal;
+use ic_cdk::api::{management_canister::main::raw_rand, time};
+use std::time::Duration;
+
+/// How long an issued nonce can be used before it expires.
+const LINK_NONCE_TTL_NS: u64 = 10 * 60 * 1_000_000_000; // 10 minutes
+
+/// Maximum number of nonces a principal can have outstanding. When exceeded, the nonce that
+/// expires first is discarded.
+const MAX_LINK_NONCES_PER_PRINCIPAL: usize = 5;
+
+/// How often expired nonces are removed from the nonce store.
+const LINK_NONCE_PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60); // 1 hour
+
+/// Maximum number of nonces inspected per message when pruning.
+const LINK_NONCE_PRUNE_BATCH_SIZE: usize = 500;
+
+type NonceKey = (StorablePrincipal, [u8; 16]);
+
+fn nonce_key(principal: &Principal, nonce: &str) -> Option<NonceKey> {
+    let nonce = hex::decode(nonce).ok()?.try_into().ok()?;
+    Some((StorablePrincipal(*principal), nonce))
+}
+
+/// Creates a random nonce for the principal and stores it until it expires.
+///
+/// The nonce is hex encoded, making it valid for use in a SIWE message.
+pub async fn create_nonce(principal: &Principal) -> Result<String, String> {
+    let (random_bytes,) = raw_rand()
+        .await
+        .map_err(|(_, m)| format!("Failed to generate nonce: {}", m))?;
+    let mut nonce = [0; 16];
+    nonce.copy_from_slice(&random_bytes[..16]);
+
+    let now = time();
+    let principal = StorablePrincipal(*principal);
+    LINK_NONCES.with_borrow_mut(|nonces| {
+        let mut outstanding: Vec<(NonceKey, u64)> = nonces
+            .range((principal, [0; 16])..=(principal, [u8::MAX; 16]))
+            .collect();
+
+        // Discard expired nonces, and the nonces expiring first to stay within the limit.
+        outstanding.sort_by_key(|(_, expires_at)| *expires_at);
+        let expired = outstanding
+            .iter()
+            .filter(|(_, expires_at)| *expires_at <= now)
+            .count();
+        let excess =
+            (outstanding.len() - expired + 1).saturating_sub(MAX_LINK_NONCES_PER_PRINCIPAL);
+        for (key, _) in outstanding.iter().take(expired + excess) {
+            nonces.remove(key);
+        }
+
+        nonces.insert((principal, nonce), now + LINK_NONCE_TTL_NS);
+    });
+
+    Ok(hex::encode(nonce))
+}
+
+/// Consumes a nonce previously issued to the principal, making sure it can only be used once.
+///
+/// # Arguments
+/// * `principal` - The principal the nonce was issued to.
+/// * `nonce` - The nonce from the signed message.
+pub fn consume_nonce(principal: &Principal, nonce: &str) -> Result<(), String> {
+    let key = nonce_key(principal, nonce).ok_or_else(|| "Nonce not found".to_string())?;
+    LINK_NONCES.with_borrow_mut(|nonces| match nonces.remove(&key) {
+        Some(expires_at) if expires_at > time() => Ok(()),
+        Some(_) => Err("Nonce expired".to_string()),
+        None => Err("Nonce not found".to_string()),
+    })
+}
+
+/// Starts the timer that periodically prunes expired nonces.
+pub fn init_nonce_pruning() {
+    ic_cdk_timers::set_timer_interval(LINK_NONCE_PRUNE_INTERVAL, || {
+        let now = time();
+        prune_map(
+            &LINK_NONCES,
+            move |_, expires_at| *expires_at <= now,
+            LINK_NONCE_PRUNE_BATCH_SIZE,
+        );
+    });
+}
@@ -0,0 +1,202 @@
+use crate::http_transform::TransformSchema;
+use crate::storable_principal::StorablePrincipal;
+use crate::{OUTCALL_METRICS, OUTCALL_QUOTAS, SETTINGS};
+use candid::{CandidType, Decode, Encode};
+use ic_cdk::api::call::CallResult;
+use ic_cdk::api::call::{msg_cycles_accept128, msg_cycles_available128, msg_cycles_refunded128};
+use ic_cdk::api::management_canister::http_request::{
+    http_request, CanisterHttpRequestArgument, HttpResponse,
+};
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::Storable;
+use serde::Deserialize;
+use std::borrow::Cow;
+
+/// Maximum response size the IC assumes when `max_response_bytes` is not set.
+const DEFAULT_MAX_RESPONSE_BYTES: u64 = 2_000_000;
+
+/// Computes the cycles needed for an HTTPS outcall, see
+/// [HTTPS outcalls cost](https://internetcomputer.org/docs/current/developer-docs/gas-cost#https-outcalls).
+///
+/// The cost depends on the number of nodes in the subnet, configured as `subnet_size` in settings.
+///
+/// The request size is the sum of the URL, headers, body, transform function name and transform
+/// context. The response is paid for up to `max_response_bytes`, whether or not the actual
+/// response is that large.
+pub fn http_request_cost(request: &CanisterHttpRequestArgument) -> u128 {
+    let headers_size: usize = request
+        .headers
+        .iter()
+        .map(|header| header.name.len() + header.value.len())
+        .sum();
+    let transform_size = request.transform.as_ref().map_or(0, |transform| {
+        transform.function.0.method.len() + transform.context.len()
+    });
+    let request_size = request.url.len()
+        + headers_size
+        + request.body.as_ref().map_or(0, |body| body.len())
+        + transform_size;
+    let response_size = request
+        .max_response_bytes
+        .unwrap_or(DEFAULT_MAX_RESPONSE_BYTES);
+    let subnet_size = SETTINGS.with_borrow(|settings_opt| {
+        u128::from(
+            settings_opt
+                .as_ref()
+                .expect("Settings should be initialized")
+                .subnet_size,
+        )
+    });
+
+    (3_000_000 + 60_000 * subnet_size) * subnet_size
+        + 400 * subnet_size * request_size as u128
+        + 800 * subnet_size * response_size as u128
+}
+
+/// Cycles spent on HTTPS outcalls of one kind.
+#[derive(CandidType, Deserialize, Debug, Clone, Default)]
+pub struct OutcallMetrics {
+    pub calls: u64,
+    pub failed_calls: u64,
+    /// Total cycles attached to calls, including cycles that were refunded.
+    pub cycles_attached: u128,
+    /// Total cycles charged for calls, that is attached cycles minus refunded cycles.
+    pub cycles_used: u128,
+    /// Cycles charged for the most recent call.
+    pub last_call_cycles_used: u128,
+}
+
+impl Storable for OutcallMetrics {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).expect("Failed to encode outcall metrics"))
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(&bytes, Self).expect("Failed to decode outcall metrics")
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+/// Makes an HTTPS outcall, attaching the cycles computed by `http_request_cost` and recording the
+/// cycles used in `OUTCALL_METRICS`, keyed by the response schema of the call.
+pub async fn http_outcall(
+    schema: TransformSchema,
+    request: CanisterHttpRequestArgument,
+) -> CallResult<(HttpResponse,)> {
+    let cycles = http_request_cost(&request);
+    let result = http_request(request, cycles).await;
+    let cycles_used = cycles.saturating_sub(msg_cycles_refunded128());
+
+    OUTCALL_METRICS.with_borrow_mut(|metrics| {
+        let key = schema.name().to_string();
+        let mut entry = metrics.get(&key).unwrap_or_default();
+        entry.calls += 1;
+        if result.is_err() {
+            entry.failed_calls += 1;
+        }
+        entry.cycles_attached += cycles;
+        entry.cycles_used += cycles_used;
+        entry.last_call_cycles_used = cycles_used;
+        metrics.insert(key, entry);
+    });
+
+    result
+}
+
+/// A payment for HTTPS outcalls made on behalf of the caller, reserved by `charge_caller`. Once the
+/// outcalls have been made, the payment is either collected or refunded.
+#[must_use]
+pub enum Charge {
+    /// The outcalls are free.
+    Free,
+    /// The outcalls are paid for with the free quota of the principal.
+    Quota {
+        principal: StorablePrincipal,
+        outcalls: u64,
+    },
+    /// The outcalls are paid for with cycles attached to the call.
+    Cycles(u128),
+}
+
+impl Charge {
+    /// Collects the payment, accepting the reserved cycles. Must be called before the call
+    /// replies, cycles that are not accepted are returned to the caller.
+    pub fn collect(self) {
+        if let Charge::Cycles(cycles) = self {
+            msg_cycles_accept128(cycles);
+        }
+    }
+
+    /// Refunds the payment, after the outcalls failed. Cycles are returned to the caller along
+    /// with the reply, the free quota is restored.
+    pub fn refund(self) {
+        if let Charge::Quota {
+            principal,
+            outcalls,
+        } = self
+        {
+            refund_outcalls(&principal, outcalls);
+        }
+    }
+
+    /// Whether the caller is paying for the outcalls.
+    pub fn is_paid(&self) -> bool {
+        !matches!(self, Charge::Free)
+    }
+}
+
+/// Makes the caller pay for HTTPS outcalls made on their behalf, if configured in settings.
+///
+/// Each principal can make `free_outcalls_per_principal` outcalls for free. After that, the caller
+/// has to attach at least `cost` cycles to the call, the total cost of the `outcalls`. When
+/// `free_outcalls_per_principal` is not set, all outcalls are free.
+///
+/// The free quota is used right away, while attached cycles are only accepted when the returned
+/// charge is collected. If the outcalls fail, the charge is refunded instead, see `Charge`.
+pub fn charge_caller(
+    principal: &StorablePrincipal,
+    outcalls: u64,
+    cost: u128,
+) -> Result<Charge, String> {
+    let free_outcalls = SETTINGS.with_borrow(|settings_opt| {
+        settings_opt
+            .as_ref()
+            .expect("Settings should be initialized")
+            .free_outcalls_per_principal
+    });
+    let Some(free_outcalls) = free_outcalls else {
+        return Ok(Charge::Free);
+    };
+    if cost == 0 {
+        return Ok(Charge::Free);
+    }
+
+    let used = OUTCALL_QUOTAS.with_borrow(|q| q.get(principal).unwrap_or(0));
+    if used.saturating_add(outcalls) <= free_outcalls {
+        OUTCALL_QUOTAS.with_borrow_mut(|q| q.insert(*principal, used + outcalls));
+        return Ok(Charge::Quota {
+            principal: *principal,
+            outcalls,
+        });
+    }
+
+    if msg_cycles_available128() < cost {
+        return Err(format!(
+            "Free quota used, attach at least {} cycles to pay for the request",
+            cost
+        ));
+    }
+
+    Ok(Charge::Cycles(cost))
+}
+
+/// Credits a principal with free outcalls, for outcalls that were paid for but failed or were not
+/// made.
+pub fn refund_outcalls(principal: &StorablePrincipal, outcalls: u64) {
+    OUTCALL_QUOTAS.with_borrow_mut(|q| {
+        if let Some(used) = q.get(principal) {
+            q.insert(*principal, used.saturating_sub(outcalls));
+        }
+    });
+}
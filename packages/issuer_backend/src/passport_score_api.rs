@@ -1,49 +1,280 @@
 use crate::eth::EthAddress;
+use crate::http_transform::TransformSchema;
+use crate::outcall::{http_outcall, http_request_cost};
+use crate::score_provider::{ScoreProvider, ScoreProviderError, ScoreResult};
+use crate::SETTINGS;
+use candid::Nat;
 use ic_cdk::api::management_canister::http_request::{
-    http_request, CanisterHttpRequestArgument, HttpMethod, TransformContext,
+    CanisterHttpRequestArgument, HttpHeader, HttpMethod,
 };
-use serde_json::Value;
+use identity_core::common::Timestamp;
+use serde::{Deserialize, Serialize};
+use std::fmt;
 
+/// Passport score responses are a few hundred bytes, plus headers and the stamp scores of the
+/// passport. Responses are paid for up to this size, so it is kept tight.
+const PASSPORT_MAX_RESPONSE_BYTES: u64 = 8_192;
+
+/// Version of the Passport API the scores are requested from, recorded with each score.
+const PASSPORT_MODEL_VERSION: &str = "passport-api-v1";
+
+/// The scoring status reported by the Gitcoin Passport API.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum PassportScoreStatus {
+    Processing,
+    Done,
+    Error,
+}
+
+/// A score response from the Gitcoin Passport API. See the
+/// [API reference](https://docs.passport.xyz/building-with-passport/passport-api/api-reference)
+/// for more information.
 ///
-/// Get the Gitcoin Passport score for an Ethereum address from the Gitcoin Passport API.
-///
-pub async fn get_passport_score(address: &EthAddress) -> Result<f32, String> {
-    // Since the Gitcoin Passport API does not accept IPv6 connections, we use a Cloudflare Worker
-    // to proxy the request to the API. Source code for the Worker can be found at
-    // https://github.com/kristoferlund/passport-score-api-proxy
-    let url = format!(
-        "https://passport-score-proxy.kristofer-977.workers.dev/submit/{address}",
+/// Only the fields used by the canister are included, other fields are removed by the `transform`
+/// query.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PassportScoreResponse {
+    pub score: Option<String>,
+    pub status: PassportScoreStatus,
+    pub last_score_timestamp: Option<String>,
+    pub expiration_date: Option<String>,
+    /// Evidence of the scoring, present when the scorer uses a threshold. Its shape depends on the
+    /// scorer, so it is kept as JSON.
+    pub evidence: Option<serde_json::Value>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub enum PassportScoreError {
+    /// The Passport API has not finished scoring the address yet.
+    Processing,
+    /// The Passport API reported an error while scoring the address.
+    ScoringFailed(String),
+    /// The Passport API response could not be parsed or did not contain a valid score.
+    InvalidResponse(String),
+    /// The request to the Passport API failed.
+    RequestFailed(String),
+}
+
+impl fmt::Display for PassportScoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PassportScoreError::Processing => write!(f, "Gitcoin Passport score is processing"),
+            PassportScoreError::ScoringFailed(e) => {
+                write!(f, "Gitcoin Passport scoring failed: {}", e)
+            }
+            PassportScoreError::InvalidResponse(e) => {
+                write!(f, "Invalid Gitcoin Passport API response: {}", e)
+            }
+            PassportScoreError::RequestFailed(e) => {
+                write!(f, "Gitcoin Passport API request failed: {}", e)
+            }
+        }
+    }
+}
+
+impl From<PassportScoreError> for ScoreProviderError {
+    fn from(error: PassportScoreError) -> Self {
+        match error {
+            PassportScoreError::Processing => ScoreProviderError::Processing,
+            PassportScoreError::ScoringFailed(e) => ScoreProviderError::ScoringFailed(e),
+            PassportScoreError::InvalidResponse(e) => ScoreProviderError::InvalidResponse(e),
+            PassportScoreError::RequestFailed(e) => ScoreProviderError::RequestFailed(e),
+        }
+    }
+}
+
+impl PassportScoreResponse {
+    /// Returns the score of a response with status `DONE`, or an error describing why there is no
+    /// score.
+    ///
+    /// # Arguments
+    ///
+    /// * `scorer_id` - The scorer the score was requested from.
+    pub fn into_score_result(
+        self,
+        scorer_id: Option<String>,
+    ) -> Result<ScoreResult, PassportScoreError> {
+        match self.status {
+            PassportScoreStatus::Processing => Err(PassportScoreError::Processing),
+            PassportScoreStatus::Error => Err(PassportScoreError::ScoringFailed(
+                self.error.unwrap_or_default(),
+            )),
+            PassportScoreStatus::Done => {
+                let score = self
+                    .score
+                    .as_deref()
+                    .and_then(|score| score.parse::<f32>().ok())
+                    .filter(|score| score.is_finite())
+                    .ok_or_else(|| {
+                        PassportScoreError::InvalidResponse("Missing or invalid score".to_string())
+                    })?;
+                Ok(ScoreResult {
+                    score,
+                    scored_at: parse_timestamp(self.last_score_timestamp.as_deref())?,
+                    expires_at: parse_timestamp(self.expiration_date.as_deref())?,
+                    scorer_id,
+                    model_version: PASSPORT_MODEL_VERSION.to_string(),
+                })
+            }
+        }
+    }
+}
+
+/// Parses an optional RFC 3339 timestamp from a Passport API response into nanoseconds since the
+/// Unix epoch.
+fn parse_timestamp(value: Option<&str>) -> Result<Option<u64>, PassportScoreError> {
+    value
+        .map(|value| {
+            let invalid_timestamp =
+                || PassportScoreError::InvalidResponse(format!("Invalid timestamp {}", value));
+            let seconds = Timestamp::parse(value)
+                .map_err(|_| invalid_timestamp())?
+                .to_unix();
+            u64::try_from(seconds)
+                .ok()
+                .and_then(|s| s.checked_mul(1_000_000_000))
+                .ok_or_else(invalid_timestamp)
+        })
+        .transpose()
+}
+
+fn scorer_id() -> Option<String> {
+    SETTINGS.with_borrow(|settings_opt| {
+        settings_opt
+            .as_ref()
+            .expect("Settings should be initialized")
+            .passport_scorer_id
+            .clone()
+    })
+}
+
+/// Scores addresses using the Gitcoin Passport API.
+pub struct GitcoinPassport;
+
+impl ScoreProvider for GitcoinPassport {
+    fn submit_cost(&self, address: &EthAddress) -> u128 {
+        http_request_cost(&passport_score_request(address, PassportEndpoint::Submit))
+    }
+
+    fn fetch_cost(&self, address: &EthAddress) -> u128 {
+        http_request_cost(&passport_score_request(address, PassportEndpoint::Score))
+    }
+
+    async fn submit(&self, address: &EthAddress) -> Result<ScoreResult, ScoreProviderError> {
+        Ok(get_passport_score(address, PassportEndpoint::Submit)
+            .await?
+            .into_score_result(scorer_id())?)
+    }
+
+    async fn fetch(&self, address: &EthAddress) -> Result<ScoreResult, ScoreProviderError> {
+        Ok(get_passport_score(address, PassportEndpoint::Score)
+            .await?
+            .into_score_result(scorer_id())?)
+    }
+}
+
+/// The Passport API endpoints used by the canister. Submitting a passport triggers (re)scoring,
+/// while fetching the score only reads the latest result.
+#[derive(Debug, Clone, Copy)]
+enum PassportEndpoint {
+    Submit,
+    Score,
+}
+
+impl PassportEndpoint {
+    fn path(&self) -> &'static str {
+        match self {
+            PassportEndpoint::Submit => "submit",
+            PassportEndpoint::Score => "score",
+        }
+    }
+}
+
+/// Percent-encodes all characters of a URL component except the unreserved ones, as defined in
+/// RFC 3986.
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+/// Builds the request for the Gitcoin Passport score of an Ethereum address from the Gitcoin
+/// Passport API, or the proxy configured in settings.
+fn passport_score_request(
+    address: &EthAddress,
+    endpoint: PassportEndpoint,
+) -> CanisterHttpRequestArgument {
+    let (api_url, scorer_id, api_key) = SETTINGS.with_borrow(|settings_opt| {
+        let settings = settings_opt
+            .as_ref()
+            .expect("Settings should be initialized");
+        (
+            settings.passport_api_url.clone(),
+            settings.passport_scorer_id.clone(),
+            settings.passport_api_key.clone(),
+        )
+    });
+
+    let mut url = format!(
+        "{api_url}/{path}/{address}",
+        api_url = api_url.trim_end_matches('/'),
+        path = endpoint.path(),
         address = address.as_str()
     );
+    if let Some(scorer_id) = scorer_id {
+        url = format!("{url}?scorer_id={}", percent_encode(&scorer_id));
+    }
+
+    // Note that request headers are visible to the nodes making the outcall.
+    let mut headers = vec![];
+    if let Some(api_key) = api_key {
+        headers.push(HttpHeader {
+            name: "X-API-KEY".to_string(),
+            value: api_key,
+        });
+    }
 
-    let request = CanisterHttpRequestArgument {
+    CanisterHttpRequestArgument {
         url,
         method: HttpMethod::GET,
         body: None,
-        max_response_bytes: None,
-        transform: Some(TransformContext::from_name(
-            "transform".to_string(),
-            serde_json::to_vec(&Vec::<u8>::new()).unwrap(),
-        )),
-        headers: vec![],
-    };
-
-    match http_request(request, 30_000_000_000).await {
+        max_response_bytes: Some(PASSPORT_MAX_RESPONSE_BYTES),
+        transform: Some(TransformSchema::PassportScore.context()),
+        headers,
+    }
+}
+
+///
+/// Request the Gitcoin Passport score for an Ethereum address from the Gitcoin Passport API, or the
+/// proxy configured in settings.
+///
+async fn get_passport_score(
+    address: &EthAddress,
+    endpoint: PassportEndpoint,
+) -> Result<PassportScoreResponse, PassportScoreError> {
+    let request = passport_score_request(address, endpoint);
+
+    match http_outcall(TransformSchema::PassportScore, request).await {
         Ok((response,)) => {
-            // Convert the response body to a string
-            let body = String::from_utf8(response.body)
-                .map_err(|_| "Couldn't read Gitcoin Passport API response".to_string())?;
-
-            // Parse the response body as JSON
-            let v: Value = serde_json::from_str(&body)
-                .map_err(|_| "Invalid JSON in Gitcoin Passport API response".to_string())?;
-
-            // Access the "score" field and convert it to a f32
-            match v["score"].as_str() {
-                Some(score) => Ok(score.parse().unwrap_or(0.0)),
-                None => Err("Gitcoin Passport API response doesn't contain a score".to_string()),
+            if response.status != Nat::from(200u16) {
+                return Err(PassportScoreError::RequestFailed(format!(
+                    "Status {}",
+                    response.status
+                )));
             }
+
+            // Parse the response body as a score response
+            serde_json::from_slice(&response.body)
+                .map_err(|e| PassportScoreError::InvalidResponse(e.to_string()))
         }
-        Err((_, m)) => Err(format!("Gitcoin Passport API request failed: {}", m)),
+        Err((_, m)) => Err(PassportScoreError::RequestFailed(m)),
     }
 }
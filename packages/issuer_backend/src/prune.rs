@@ -0,0 +1,104 @@
+use ic_stable_structures::{Memory, StableBTreeMap, Storable};
+use std::cell::RefCell;
+use std::ops::Bound;
+use std::thread::LocalKey;
+use std::time::Duration;
+
+/// Removes the entries of `map` for which `is_expired` returns true, inspecting at most `batch`
+/// entries per message. If entries remain, pruning continues in a new message, keeping the
+/// instructions used per message bounded.
+pub fn prune_map<K, V, M, F>(
+    map: &'static LocalKey<RefCell<StableBTreeMap<K, V, M>>>,
+    is_expired: F,
+    batch: usize,
+) where
+    K: Storable + Ord + Clone + 'static,
+    V: Storable + 'static,
+    M: Memory + 'static,
+    F: Fn(&K, &V) -> bool + Clone + 'static,
+{
+    prune_map_from(map, None, is_expired, batch);
+}
+
+fn prune_map_from<K, V, M, F>(
+    map: &'static LocalKey<RefCell<StableBTreeMap<K, V, M>>>,
+    start: Option<K>,
+    is_expired: F,
+    batch: usize,
+) where
+    K: Storable + Ord + Clone + 'static,
+    V: Storable + 'static,
+    M: Memory + 'static,
+    F: Fn(&K, &V) -> bool + Clone + 'static,
+{
+    let next = map.with_borrow_mut(|map| prune_batch(map, start, &is_expired, batch));
+
+    if let Some(next) = next {
+        ic_cdk_timers::set_timer(Duration::ZERO, move || {
+            prune_map_from(map, Some(next), is_expired, batch)
+        });
+    }
+}
+
+/// Removes the expired entries among the first `batch` entries starting at `start`. Returns the
+/// key to continue from, if any entries remain.
+fn prune_batch<K, V, M>(
+    map: &mut StableBTreeMap<K, V, M>,
+    start: Option<K>,
+    is_expired: impl Fn(&K, &V) -> bool,
+    batch: usize,
+) -> Option<K>
+where
+    K: Storable + Ord + Clone,
+    V: Storable,
+    M: Memory,
+{
+    let start = start.map_or(Bound::Unbounded, Bound::Included);
+    let mut entries: Vec<(K, V)> = map
+        .range((start, Bound::Unbounded))
+        .take(batch + 1)
+        .collect();
+    let next = if entries.len() > batch {
+        entries.pop().map(|(key, _)| key)
+    } else {
+        None
+    };
+    for (key, value) in entries {
+        if is_expired(&key, &value) {
+            map.remove(&key);
+        }
+    }
+    next
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ic_stable_structures::DefaultMemoryImpl;
+
+    #[test]
+    fn prunes_in_batches() {
+        let mut map: StableBTreeMap<u64, u64, _> =
+            StableBTreeMap::new(DefaultMemoryImpl::default());
+        for key in 0..10 {
+            map.insert(key, key % 2);
+        }
+        let is_expired = |_: &u64, expired: &u64| *expired == 1;
+
+        let mut start = None;
+        let mut batches = 0;
+        loop {
+            batches += 1;
+            start = prune_batch(&mut map, start, is_expired, 4);
+            if start.is_none() {
+                break;
+            }
+        }
+
+        assert_eq!(batches, 3);
+        assert_eq!(
+            map.iter().map(|(key, _)| key).collect::<Vec<_>>(),
+            vec![0, 2, 4, 6, 8]
+        );
+    }
+}
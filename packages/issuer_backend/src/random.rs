@@ -0,0 +1,50 @@
+use ic_cdk::api::management_canister::main::raw_rand;
+use std::time::Duration;
+
+/// Time to wait before requesting random bytes again after `raw_rand` failed.
+const RANDOM_SECRET_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// Generates a random 32 byte secret using `raw_rand` and passes it to `save`, unless
+/// `is_generated` returns true. Random bytes cannot be requested during init or post upgrade, so
+/// the secret is generated by a timer that fires immediately after. If `raw_rand` fails, it is
+/// retried after `RANDOM_SECRET_RETRY_DELAY` until it succeeds.
+///
+/// # Arguments
+///
+/// * `name` - Name of the secret, used in log messages.
+/// * `is_generated` - Whether the secret has been generated already.
+/// * `save` - Saves the generated secret.
+pub fn init_random_secret(name: &'static str, is_generated: fn() -> bool, save: fn([u8; 32])) {
+    schedule_random_secret(Duration::ZERO, name, is_generated, save);
+}
+
+fn schedule_random_secret(
+    delay: Duration,
+    name: &'static str,
+    is_generated: fn() -> bool,
+    save: fn([u8; 32]),
+) {
+    ic_cdk_timers::set_timer(delay, move || {
+        ic_cdk::spawn(async move {
+            if is_generated() {
+                return;
+            }
+
+            match raw_rand().await {
+                Ok((random_bytes,)) if random_bytes.len() >= 32 => {
+                    let mut secret = [0; 32];
+                    secret.copy_from_slice(&random_bytes[..32]);
+                    save(secret);
+                }
+                result => {
+                    ic_cdk::api::print(format!(
+                        "Failed to generate {}, retrying: {:?}",
+                        name,
+                        result.err()
+                    ));
+                    schedule_random_secret(RANDOM_SECRET_RETRY_DELAY, name, is_generated, save);
+                }
+            }
+        });
+    });
+}
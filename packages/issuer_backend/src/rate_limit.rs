@@ -0,0 +1,133 @@
+use crate::prune::prune_map;
+use crate::storable_principal::StorablePrincipal;
+use crate::{GLOBAL_LINK_BUCKET, PRINCIPAL_LINK_ATTEMPTS, SETTINGS};
+use candid::{CandidType, Principal};
+use ic_cdk::api::{is_controller, time};
+use serde::Deserialize;
+use std::fmt;
+use std::time::Duration;
+
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+/// How often link attempts of past windows are removed.
+const LINK_ATTEMPTS_PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60); // 1 hour
+
+/// Maximum number of principals inspected per message when pruning.
+const LINK_ATTEMPTS_PRUNE_BATCH_SIZE: usize = 500;
+
+/// Returned when a link attempt exceeds the configured rate limits.
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct RateLimitError {
+    /// Seconds until the next attempt is allowed.
+    pub retry_after_secs: u64,
+}
+
+impl fmt::Display for RateLimitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Too many attempts, try again in {} seconds",
+            self.retry_after_secs
+        )
+    }
+}
+
+impl From<RateLimitError> for String {
+    fn from(error: RateLimitError) -> Self {
+        error.to_string()
+    }
+}
+
+fn retry_after_secs(retry_at: u64, now: u64) -> u64 {
+    retry_at.saturating_sub(now).div_ceil(NANOS_PER_SEC)
+}
+
+/// Limits the number of link attempts, each of which can result in paid HTTPS outcalls. Creating
+/// nonces, unlinking and completing transfers count as link attempts as well.
+///
+/// Two limits apply:
+/// - Each principal can make `principal_link_attempts` attempts per window of
+///   `principal_link_window_ns`.
+/// - All principals together share a token bucket holding at most `global_link_bucket_capacity`
+///   tokens, refilled with one token every `global_link_refill_interval_ns`.
+///
+/// A link attempt can result in more than one outcall, `attempts` is the number of attempts the
+/// outcalls are counted as. The attempts are only counted if both limits allow all of them.
+/// Controllers are exempt from rate limiting.
+pub fn check_link_rate_limit(principal: &Principal, attempts: u32) -> Result<(), RateLimitError> {
+    if is_controller(principal) {
+        return Ok(());
+    }
+
+    let (max_attempts, window, capacity, refill_interval) = SETTINGS.with_borrow(|settings_opt| {
+        let settings = settings_opt
+            .as_ref()
+            .expect("Settings should be initialized");
+        (
+            settings.principal_link_attempts,
+            settings.principal_link_window_ns,
+            settings.global_link_bucket_capacity,
+            settings.global_link_refill_interval_ns,
+        )
+    });
+    let now = time();
+    let principal = StorablePrincipal(*principal);
+
+    // Attempts by the principal in the current window, a new window starts after the previous one
+    // has passed.
+    let (window_start, count) = PRINCIPAL_LINK_ATTEMPTS
+        .with_borrow(|a| a.get(&principal))
+        .filter(|(window_start, _)| window_start.saturating_add(window) > now)
+        .unwrap_or((now, 0));
+    if count.saturating_add(attempts) > max_attempts {
+        return Err(RateLimitError {
+            retry_after_secs: retry_after_secs(window_start.saturating_add(window), now),
+        });
+    }
+
+    // Refill the global bucket with the tokens accumulated since the last refill.
+    let (tokens, last_refill) = GLOBAL_LINK_BUCKET.with_borrow(|b| *b.get());
+    let (tokens, last_refill) = match now.saturating_sub(last_refill).checked_div(refill_interval) {
+        Some(refills) if tokens.saturating_add(refills) < capacity => {
+            (tokens + refills, last_refill + refills * refill_interval)
+        }
+        _ => (capacity, now),
+    };
+    let attempts = u64::from(attempts);
+    if tokens < attempts {
+        let missing_tokens = attempts - tokens;
+        return Err(RateLimitError {
+            retry_after_secs: retry_after_secs(
+                last_refill.saturating_add(missing_tokens.saturating_mul(refill_interval)),
+                now,
+            ),
+        });
+    }
+
+    PRINCIPAL_LINK_ATTEMPTS
+        .with_borrow_mut(|a| a.insert(principal, (window_start, count + attempts as u32)));
+    GLOBAL_LINK_BUCKET.with_borrow_mut(|b| {
+        b.set((tokens - attempts, last_refill))
+            .expect("Failed to save global link bucket");
+    });
+
+    Ok(())
+}
+
+/// Starts the timer that periodically prunes link attempts of past windows.
+pub fn init_link_attempts_pruning() {
+    ic_cdk_timers::set_timer_interval(LINK_ATTEMPTS_PRUNE_INTERVAL, || {
+        let window = SETTINGS.with_borrow(|settings_opt| {
+            settings_opt
+                .as_ref()
+                .expect("Settings should be initialized")
+                .principal_link_window_ns
+        });
+        let now = time();
+        prune_map(
+            &PRINCIPAL_LINK_ATTEMPTS,
+            move |_, (window_start, _)| window_start.saturating_add(window) <= now,
+            LINK_ATTEMPTS_PRUNE_BATCH_SIZE,
+        );
+    });
+}
@@ -0,0 +1,157 @@
+use crate::eth::{keccak256, EthAddress};
+use crate::passport_score_api::GitcoinPassport;
+use crate::SETTINGS;
+use candid::CandidType;
+use serde::Deserialize;
+use std::fmt;
+
+/// Selects the provider used to score Ethereum addresses.
+#[derive(CandidType, Deserialize, Debug, Clone, Copy, Default)]
+pub enum ScoreProviderKind {
+    /// The Gitcoin Passport API, or the proxy configured in settings.
+    #[default]
+    GitcoinPassport,
+    /// A deterministic in-canister provider that makes no HTTPS outcalls. Meant for local testing
+    /// only, see `MockScoreProvider`.
+    Mock,
+}
+
+/// A final score for an Ethereum address, as returned by a score provider.
+#[derive(Debug, Clone)]
+pub struct ScoreResult {
+    pub score: f32,
+    /// When the provider calculated the score, in nanoseconds since the Unix epoch.
+    pub scored_at: Option<u64>,
+    /// When the score expires according to the provider, in nanoseconds since the Unix epoch.
+    pub expires_at: Option<u64>,
+    /// The scorer used by the provider, for providers with more than one scorer.
+    pub scorer_id: Option<String>,
+    /// The version of the provider API or scoring model that produced the score.
+    pub model_version: String,
+}
+
+/// Errors returned by score providers. Providers convert their own errors into these.
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub enum ScoreProviderError {
+    /// The provider has not finished scoring the address yet.
+    Processing,
+    /// The provider reported an error while scoring the address.
+    ScoringFailed(String),
+    /// The provider response could not be parsed or did not contain a valid score.
+    InvalidResponse(String),
+    /// The request to the provider failed.
+    RequestFailed(String),
+}
+
+impl fmt::Display for ScoreProviderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScoreProviderError::Processing => write!(f, "Score is processing"),
+            ScoreProviderError::ScoringFailed(e) => write!(f, "Scoring failed: {}", e),
+            ScoreProviderError::InvalidResponse(e) => {
+                write!(f, "Invalid score provider response: {}", e)
+            }
+            ScoreProviderError::RequestFailed(e) => {
+                write!(f, "Score provider request failed: {}", e)
+            }
+        }
+    }
+}
+
+/// A source of humanity or reputation scores for Ethereum addresses.
+///
+/// Scoring can take a while. Providers return `ScoreProviderError::Processing` when a score is not
+/// ready yet, after which `fetch` is polled until it returns a final score.
+pub trait ScoreProvider {
+    /// The cycles it costs the canister to submit an address for scoring.
+    fn submit_cost(&self, address: &EthAddress) -> u128;
+
+    /// The cycles it costs the canister to fetch the score of an address.
+    fn fetch_cost(&self, address: &EthAddress) -> u128;
+
+    /// Submits an address for (re)scoring and returns the score if it is available right away.
+    async fn submit(&self, address: &EthAddress) -> Result<ScoreResult, ScoreProviderError>;
+
+    /// Fetches the latest score of a previously submitted address.
+    async fn fetch(&self, address: &EthAddress) -> Result<ScoreResult, ScoreProviderError>;
+}
+
+/// A score provider for local testing, with dfx or PocketIC, that makes no HTTPS outcalls. The score
+/// of an address is derived from its hash, so the same address always gets the same score, between
+/// 0 and 50 with two decimals.
+pub struct MockScoreProvider;
+
+impl MockScoreProvider {
+    fn score(address: &EthAddress) -> ScoreResult {
+        let hash = keccak256(address.as_str().to_lowercase().as_bytes());
+        let hundredths = u16::from_be_bytes([hash[0], hash[1]]) % 5001;
+        ScoreResult {
+            score: f32::from(hundredths) / 100.0,
+            scored_at: None,
+            expires_at: None,
+            scorer_id: None,
+            model_version: "mock-v1".to_string(),
+        }
+    }
+}
+
+impl ScoreProvider for MockScoreProvider {
+    fn submit_cost(&self, _address: &EthAddress) -> u128 {
+        0
+    }
+
+    fn fetch_cost(&self, _address: &EthAddress) -> u128 {
+        0
+    }
+
+    async fn submit(&self, address: &EthAddress) -> Result<ScoreResult, ScoreProviderError> {
+        Ok(Self::score(address))
+    }
+
+    async fn fetch(&self, address: &EthAddress) -> Result<ScoreResult, ScoreProviderError> {
+        Ok(Self::score(address))
+    }
+}
+
+fn score_provider_kind() -> ScoreProviderKind {
+    SETTINGS.with_borrow(|settings_opt| {
+        settings_opt
+            .as_ref()
+            .expect("Settings should be initialized")
+            .score_provider
+    })
+}
+
+/// The cycles it costs to submit an address for scoring using the score provider configured in
+/// settings.
+pub fn submit_score_cost(address: &EthAddress) -> u128 {
+    match score_provider_kind() {
+        ScoreProviderKind::GitcoinPassport => GitcoinPassport.submit_cost(address),
+        ScoreProviderKind::Mock => MockScoreProvider.submit_cost(address),
+    }
+}
+
+/// The cycles it costs to fetch the score of an address using the score provider configured in
+/// settings.
+pub fn fetch_score_cost(address: &EthAddress) -> u128 {
+    match score_provider_kind() {
+        ScoreProviderKind::GitcoinPassport => GitcoinPassport.fetch_cost(address),
+        ScoreProviderKind::Mock => MockScoreProvider.fetch_cost(address),
+    }
+}
+
+/// Submits an address for scoring using the score provider configured in settings.
+pub async fn submit_score(address: &EthAddress) -> Result<ScoreResult, ScoreProviderError> {
+    match score_provider_kind() {
+        ScoreProviderKind::GitcoinPassport => GitcoinPassport.submit(address).await,
+        ScoreProviderKind::Mock => MockScoreProvider.submit(address).await,
+    }
+}
+
+/// Fetches the latest score of an address using the score provider configured in settings.
+pub async fn fetch_score(address: &EthAddress) -> Result<ScoreResult, ScoreProviderError> {
+    match score_provider_kind() {
+        ScoreProviderKind::GitcoinPassport => GitcoinPassport.fetch(address).await,
+        ScoreProviderKind::Mock => MockScoreProvider.fetch(address).await,
+    }
+}
@@ -0,0 +1,76 @@
+use crate::score_provider::ScoreResult;
+use candid::{CandidType, Decode, Encode};
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::Storable;
+use serde::Deserialize;
+use std::borrow::Cow;
+
+/// The score of a linked Ethereum address, together with what is needed to reason about its
+/// freshness and origin.
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct ScoreRecord {
+    pub score: f32,
+    /// When the score was fetched from the score provider, in nanoseconds since the Unix epoch.
+    /// Zero for scores fetched before this was recorded.
+    pub fetched_at: u64,
+    /// When the score provider calculated the score, in nanoseconds since the Unix epoch.
+    pub scored_at: Option<u64>,
+    /// When the score expires according to the score provider, in nanoseconds since the Unix epoch.
+    pub expires_at: Option<u64>,
+    /// The scorer used by the score provider.
+    pub scorer_id: Option<String>,
+    /// The version of the score provider API or scoring model that produced the score.
+    pub model_version: Option<String>,
+    /// The hash of the address the score belongs to, see `address_hash::address_hash`.
+    pub address_hash: [u8; 32],
+}
+
+impl ScoreRecord {
+    pub fn new(result: &ScoreResult, address_hash: [u8; 32], fetched_at: u64) -> Self {
+        ScoreRecord {
+            score: result.score,
+            fetched_at,
+            scored_at: result.scored_at,
+            expires_at: result.expires_at,
+            scorer_id: result.scorer_id.clone(),
+            model_version: Some(result.model_version.clone()),
+            address_hash,
+        }
+    }
+
+    /// A record for a score stored before anything but the score itself was recorded.
+    pub fn legacy(score: f32, address_hash: [u8; 32]) -> Self {
+        ScoreRecord {
+            score,
+            fetched_at: 0,
+            scored_at: None,
+            expires_at: None,
+            scorer_id: None,
+            model_version: None,
+            address_hash,
+        }
+    }
+}
+
+/// Versions of the score record, making it possible to change the record without migrating stable
+/// memory. New versions are added as variants, older versions are converted when read.
+#[derive(CandidType, Deserialize)]
+enum VersionedScoreRecord {
+    V1(ScoreRecord),
+}
+
+impl Storable for ScoreRecord {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(
+            Encode!(&VersionedScoreRecord::V1(self.clone())).expect("Failed to encode score"),
+        )
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        match Decode!(&bytes, VersionedScoreRecord).expect("Failed to decode score") {
+            VersionedScoreRecord::V1(record) => record,
+        }
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
@@ -0,0 +1,139 @@
+use crate::address_hash::decrypt_address;
+use crate::link::register_score;
+use crate::link_guard::LinkGuard;
+use crate::score_provider::{submit_score, submit_score_cost};
+use crate::{
+    ADDRESS_REFRESH, ADDRESS_REFRESH_QUEUE, ETH_PRINCIPAL, SCORE_REFRESH_IN_PROGRESS, SETTINGS,
+};
+use ic_cdk::api::time;
+use std::time::Duration;
+
+/// Time between runs of the score refresher.
+const SCORE_REFRESH_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// Marks the score refresher as not running when dropped, also if a run traps.
+struct RefreshGuard;
+
+impl Drop for RefreshGuard {
+    fn drop(&mut self) {
+        SCORE_REFRESH_IN_PROGRESS.set(false);
+    }
+}
+
+/// Records the time the score of a linked address was fetched or attempted to be refreshed, along
+/// with the encrypted address needed to refresh it.
+pub fn schedule_refresh(address_hash: [u8; 32], encrypted_address: [u8; 20], refreshed_at: u64) {
+    let previous = ADDRESS_REFRESH
+        .with_borrow_mut(|a| a.insert(address_hash, (encrypted_address, refreshed_at)));
+    ADDRESS_REFRESH_QUEUE.with_borrow_mut(|q| {
+        if let Some((_, previous_refreshed_at)) = previous {
+            q.remove(&(previous_refreshed_at, address_hash));
+        }
+        q.insert((refreshed_at, address_hash), ());
+    });
+}
+
+/// Stops refreshing the score of an address, once it is unlinked.
+pub fn cancel_refresh(address_hash: &[u8; 32]) {
+    if let Some((_, refreshed_at)) = ADDRESS_REFRESH.with_borrow_mut(|a| a.remove(address_hash)) {
+        ADDRESS_REFRESH_QUEUE.with_borrow_mut(|q| q.remove(&(refreshed_at, *address_hash)));
+    }
+}
+
+/// Returns the hashes and encrypted addresses of at most `batch_size` linked addresses whose score
+/// was last fetched, or attempted to be refreshed, more than `max_age` nanoseconds ago. Only the
+/// stale entries of `ADDRESS_REFRESH_QUEUE` are read, oldest first.
+fn stale_addresses(max_age: u64, batch_size: u32) -> Vec<([u8; 32], [u8; 20])> {
+    let Some(stale_before) = time().checked_sub(max_age) else {
+        return vec![];
+    };
+    let address_hashes: Vec<[u8; 32]> = ADDRESS_REFRESH_QUEUE.with_borrow(|q| {
+        q.range((0, [0; 32])..=(stale_before, [u8::MAX; 32]))
+            .take(batch_size as usize)
+            .map(|((_, address_hash), _)| address_hash)
+            .collect()
+    });
+    ADDRESS_REFRESH.with_borrow(|a| {
+        address_hashes
+            .into_iter()
+            .filter_map(|address_hash| {
+                a.get(&address_hash)
+                    .map(|(encrypted_address, _)| (address_hash, encrypted_address))
+            })
+            .collect()
+    })
+}
+
+/// Refreshes the scores of a batch of linked addresses whose score is older than the configured
+/// maximum age, re-submitting them to the score provider. The batch is cut short when the cycles
+/// spent on outcalls would exceed the configured budget.
+///
+/// Addresses that are being linked by a user at the same time are skipped and retried in the next
+/// run. Addresses that fail to refresh are retried once the maximum age has passed again, so they
+/// do not block other addresses from being refreshed. Addresses that cannot be decrypted are no
+/// longer refreshed.
+async fn refresh_stale_scores() {
+    let (max_age, batch_size, cycles_budget) = SETTINGS.with_borrow(|settings_opt| {
+        let settings = settings_opt
+            .as_ref()
+            .expect("Settings should be initialized");
+        (
+            settings.score_refresh_max_age_ns,
+            settings.score_refresh_batch_size,
+            settings.score_refresh_cycles_budget,
+        )
+    });
+    let Some(max_age) = max_age else {
+        return;
+    };
+
+    let mut cycles_spent: u128 = 0;
+    for (address_hash, encrypted_address) in stale_addresses(max_age, batch_size) {
+        let Some(principal) = ETH_PRINCIPAL.with_borrow(|e| e.get(&address_hash)) else {
+            cancel_refresh(&address_hash);
+            continue;
+        };
+        let address = match decrypt_address(&encrypted_address, &address_hash) {
+            Ok(address) => address,
+            Err(e) => {
+                // Decrypting fails the same way every time, stop refreshing the address so it
+                // does not take up the batch. The address is refreshed again once relinked.
+                ic_cdk::api::print(format!("Failed to decrypt address: {}", e));
+                cancel_refresh(&address_hash);
+                continue;
+            }
+        };
+
+        cycles_spent += submit_score_cost(&address);
+        if cycles_spent > cycles_budget {
+            break;
+        }
+
+        let Ok(_guard) = LinkGuard::acquire(&principal.0, &address_hash) else {
+            continue;
+        };
+        let result = submit_score(&address)
+            .await
+            .map_err(|e| e.to_string())
+            .and_then(|result| register_score(&principal.0, &address, &result));
+        if let Err(e) = result {
+            ic_cdk::api::print(format!("Failed to refresh passport score: {}", e));
+            schedule_refresh(address_hash, encrypted_address, time());
+        }
+    }
+}
+
+/// Starts the timer that periodically refreshes stale scores, see `refresh_stale_scores`. Timers do
+/// not survive upgrades, so this needs to be called both on init and post upgrade.
+pub fn init_score_refresh() {
+    ic_cdk_timers::set_timer_interval(SCORE_REFRESH_INTERVAL, || {
+        // Skip this run if the previous one has not finished yet.
+        if SCORE_REFRESH_IN_PROGRESS.replace(true) {
+            return;
+        }
+        ic_cdk::spawn(async {
+            let _guard = RefreshGuard;
+            refresh_stale_scores().await;
+        });
+    });
+}
@@ -1,28 +1,280 @@
 use crate::{
+    address_hash::init_address_pepper,
     certified_data::init_assets,
-    settings::{Settings, SettingsInput},
-    SETTINGS,
+    link::init_transfer_pruning,
+    nonce::init_nonce_pruning,
+    rate_limit::init_link_attempts_pruning,
+    score_provider::ScoreProviderKind,
+    score_record::ScoreRecord,
+    score_refresh::init_score_refresh,
+    settings::{
+        persist_settings, secs_to_ns, stored_settings, ScorePolicy, Settings, SettingsInput,
+        UnlinkAuthorization, DEFAULT_CREDENTIAL_MAX_VALIDITY_SECS,
+        DEFAULT_CREDENTIAL_MIN_VALIDITY_SECS, DEFAULT_GLOBAL_LINK_BUCKET_CAPACITY,
+        DEFAULT_GLOBAL_LINK_REFILL_INTERVAL_SECS, DEFAULT_MAX_LINKED_ADDRESSES,
+        DEFAULT_PASSPORT_API_URL, DEFAULT_PRINCIPAL_LINK_ATTEMPTS,
+        DEFAULT_PRINCIPAL_LINK_WINDOW_SECS, DEFAULT_SCORE_REFRESH_BATCH_SIZE,
+        DEFAULT_SCORE_REFRESH_CYCLES_BUDGET, DEFAULT_SIWE_CHAIN_ID, DEFAULT_SUBNET_SIZE,
+    },
+    storable_principal::StorablePrincipal,
+    vc::{init_credential_salt, normalize_origin},
+    ETH_PRINCIPAL, MEMORY_MANAGER, PRINCIPAL_ADDRESSES, PRINCIPAL_PRIMARY, SETTINGS,
 };
+use candid::Principal;
 use canister_sig_util::extract_raw_root_pk_from_der;
 use ic_cdk::{init, post_upgrade};
+use ic_stable_structures::memory_manager::{MemoryId, VirtualMemory};
+use ic_stable_structures::{DefaultMemoryImpl, StableBTreeMap, Storable};
+
+// Memories holding data keyed by principals truncated to 29 bytes, see `migrate_principal_keys`.
+const LEGACY_PRINCIPAL_SCORE_MEMORY_ID: MemoryId = MemoryId::new(0);
+const LEGACY_ETH_PRINCIPAL_MEMORY_ID: MemoryId = MemoryId::new(1);
+
+// Number of entries read from a legacy map at a time, see `drain_legacy_map`.
+const LEGACY_MIGRATION_BATCH_SIZE: usize = 1_000;
 
 #[init]
 async fn init(settings_input: SettingsInput) {
-    save_settings(settings_input);
+    save_settings(settings_input, None);
     init_assets();
+    init_nonce_pruning();
+    init_transfer_pruning();
+    init_link_attempts_pruning();
+    init_score_refresh();
+    init_address_pepper();
+    init_credential_salt();
 }
 
+/// Settings are kept across upgrades. When settings are passed as arguments, the fields that are
+/// set replace the stored settings. Fields that are not set keep their stored value, including
+/// changes made using `settings_update`.
 #[post_upgrade]
-fn upgrade(settings_input: SettingsInput) {
-    save_settings(settings_input);
+fn upgrade(settings_input: Option<SettingsInput>) {
+    let stored_settings = stored_settings();
+    match settings_input {
+        Some(settings_input) => save_settings(settings_input, stored_settings),
+        None => {
+            let settings = stored_settings.expect(
+                "Settings should be passed when upgrading a canister without stored settings",
+            );
+            SETTINGS.with_borrow_mut(|settings_opt| *settings_opt = Some(settings));
+        }
+    }
+    migrate_principal_keys();
     init_assets();
+    init_nonce_pruning();
+    init_transfer_pruning();
+    init_link_attempts_pruning();
+    init_score_refresh();
+    init_address_pepper();
+    init_credential_salt();
+}
+
+/// The settings used for fields that are not set in the init arguments.
+fn default_settings(settings_input: &SettingsInput) -> Settings {
+    let canister_origin = format!("https://{}.icp0.io", ic_cdk::api::id());
+    Settings {
+        ii_canister_id: settings_input.ii_canister_id,
+        ic_root_key_raw: vec![],
+        siwe_domain: settings_input.siwe_domain.clone(),
+        siwe_uri: settings_input.siwe_uri.clone(),
+        siwe_chain_id: DEFAULT_SIWE_CHAIN_ID,
+        eth_rpc_url: None,
+        max_linked_addresses: DEFAULT_MAX_LINKED_ADDRESSES,
+        score_policy: ScorePolicy::default(),
+        unlink_authorization: UnlinkAuthorization::default(),
+        unlink_cooldown_ns: 0,
+        passport_api_url: DEFAULT_PASSPORT_API_URL.to_string(),
+        passport_scorer_id: None,
+        passport_api_key: None,
+        score_provider: ScoreProviderKind::default(),
+        free_outcalls_per_principal: None,
+        subnet_size: DEFAULT_SUBNET_SIZE,
+        principal_link_attempts: DEFAULT_PRINCIPAL_LINK_ATTEMPTS,
+        principal_link_window_ns: DEFAULT_PRINCIPAL_LINK_WINDOW_SECS * 1_000_000_000,
+        global_link_bucket_capacity: DEFAULT_GLOBAL_LINK_BUCKET_CAPACITY,
+        global_link_refill_interval_ns: DEFAULT_GLOBAL_LINK_REFILL_INTERVAL_SECS * 1_000_000_000,
+        score_refresh_max_age_ns: None,
+        score_refresh_batch_size: DEFAULT_SCORE_REFRESH_BATCH_SIZE,
+        score_refresh_cycles_budget: DEFAULT_SCORE_REFRESH_CYCLES_BUDGET.into(),
+        credential_max_score_age_ns: None,
+        issuer_url: canister_origin.clone(),
+        derivation_origin: canister_origin,
+        frontend_hostnames: vec![],
+        credential_min_validity_ns: DEFAULT_CREDENTIAL_MIN_VALIDITY_SECS * 1_000_000_000,
+        credential_max_validity_ns: DEFAULT_CREDENTIAL_MAX_VALIDITY_SECS * 1_000_000_000,
+    }
+}
+
+/// Saves the settings passed as arguments on top of the current settings, or the defaults if
+/// there are none, and persists them. Traps if the resulting settings are invalid.
+fn save_settings(settings_input: SettingsInput, current: Option<Settings>) {
+    let settings = merge_settings(settings_input, current)
+        .unwrap_or_else(|e| ic_cdk::trap(&format!("Invalid settings: {}", e)));
+
+    SETTINGS.with_borrow_mut(|settings_opt| *settings_opt = Some(settings));
+    persist_settings();
+}
+
+/// Applies the settings passed as arguments on top of the current settings, or the defaults if
+/// there are none.
+fn merge_settings(
+    settings_input: SettingsInput,
+    current: Option<Settings>,
+) -> Result<Settings, String> {
+    let mut settings = current.unwrap_or_else(|| default_settings(&settings_input));
+
+    settings.ic_root_key_raw =
+        extract_raw_root_pk_from_der(&settings_input.ic_root_key_der).unwrap();
+    settings.ii_canister_id = settings_input.ii_canister_id;
+    settings.siwe_domain = settings_input.siwe_domain;
+    settings.siwe_uri = settings_input.siwe_uri;
+    if let Some(chain_id) = settings_input.siwe_chain_id {
+        settings.siwe_chain_id = chain_id;
+    }
+    if let Some(eth_rpc_url) = settings_input.eth_rpc_url {
+        settings.eth_rpc_url = Some(eth_rpc_url);
+    }
+    if let Some(max_linked_addresses) = settings_input.max_linked_addresses {
+        settings.max_linked_addresses = max_linked_addresses;
+    }
+    if let Some(score_policy) = settings_input.score_policy {
+        settings.score_policy = score_policy;
+    }
+    if let Some(unlink_authorization) = settings_input.unlink_authorization {
+        settings.unlink_authorization = unlink_authorization;
+    }
+    if let Some(cooldown_secs) = settings_input.unlink_cooldown_secs {
+        settings.unlink_cooldown_ns = secs_to_ns(cooldown_secs)?;
+    }
+    if let Some(passport_api_url) = settings_input.passport_api_url {
+        settings.passport_api_url = passport_api_url;
+    }
+    if let Some(passport_scorer_id) = settings_input.passport_scorer_id {
+        settings.passport_scorer_id = Some(passport_scorer_id);
+    }
+    if let Some(passport_api_key) = settings_input.passport_api_key {
+        settings.passport_api_key = Some(passport_api_key);
+    }
+    if let Some(score_provider) = settings_input.score_provider {
+        settings.score_provider = score_provider;
+    }
+    if let Some(free_outcalls) = settings_input.free_outcalls_per_principal {
+        settings.free_outcalls_per_principal = Some(free_outcalls);
+    }
+    if let Some(subnet_size) = settings_input.subnet_size {
+        settings.subnet_size = subnet_size;
+    }
+    if let Some(attempts) = settings_input.principal_link_attempts {
+        settings.principal_link_attempts = attempts;
+    }
+    if let Some(window_secs) = settings_input.principal_link_window_secs {
+        settings.principal_link_window_ns = secs_to_ns(window_secs)?;
+    }
+    if let Some(capacity) = settings_input.global_link_bucket_capacity {
+        settings.global_link_bucket_capacity = capacity;
+    }
+    if let Some(refill_interval_secs) = settings_input.global_link_refill_interval_secs {
+        settings.global_link_refill_interval_ns = secs_to_ns(refill_interval_secs)?;
+    }
+    if let Some(max_age_secs) = settings_input.score_refresh_max_age_secs {
+        settings.score_refresh_max_age_ns = (max_age_secs > 0)
+            .then(|| secs_to_ns(max_age_secs))
+            .transpose()?;
+    }
+    if let Some(batch_size) = settings_input.score_refresh_batch_size {
+        settings.score_refresh_batch_size = batch_size;
+    }
+    if let Some(cycles_budget) = settings_input.score_refresh_cycles_budget {
+        settings.score_refresh_cycles_budget = cycles_budget.into();
+    }
+    if let Some(max_age_secs) = settings_input.credential_max_score_age_secs {
+        settings.credential_max_score_age_ns = (max_age_secs > 0)
+            .then(|| secs_to_ns(max_age_secs))
+            .transpose()?;
+    }
+    if let Some(issuer_url) = settings_input.issuer_url {
+        settings.issuer_url = issuer_url;
+    }
+    if let Some(derivation_origin) = settings_input.derivation_origin {
+        settings.derivation_origin = derivation_origin;
+    }
+    settings.derivation_origin = normalize_origin(&settings.derivation_origin);
+    if let Some(frontend_hostnames) = settings_input.frontend_hostnames {
+        settings.frontend_hostnames = frontend_hostnames
+            .iter()
+            .map(|hostname| normalize_origin(hostname))
+            .collect();
+    }
+    if let Some(min_validity_secs) = settings_input.credential_min_validity_secs {
+        settings.credential_min_validity_ns = secs_to_ns(min_validity_secs)?;
+    }
+    if let Some(max_validity_secs) = settings_input.credential_max_validity_secs {
+        settings.credential_max_validity_ns = secs_to_ns(max_validity_secs)?;
+    }
+
+    settings.validate()?;
+    Ok(settings)
 }
 
-fn save_settings(settings_input: SettingsInput) {
-    SETTINGS.with_borrow_mut(|settings| {
-        *settings = Some(Settings {
-            ii_canister_id: settings_input.ii_canister_id,
-            ic_root_key_raw: extract_raw_root_pk_from_der(&settings_input.ic_root_key_der).unwrap(),
-        });
-    });
+/// Opens a map stored in a legacy memory.
+fn legacy_map<K, V>(memory_id: MemoryId) -> StableBTreeMap<K, V, VirtualMemory<DefaultMemoryImpl>>
+where
+    K: Storable + Ord + Clone,
+    V: Storable,
+{
+    StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(memory_id)))
+}
+
+/// Removes all entries from a map stored in a legacy memory, passing each of them to `f`. Entries
+/// are read in batches, so only a bounded number of them is held on the heap at a time.
+fn drain_legacy_map<K, V>(memory_id: MemoryId, mut f: impl FnMut(K, V))
+where
+    K: Storable + Ord + Clone,
+    V: Storable,
+{
+    let mut map = legacy_map::<K, V>(memory_id);
+    loop {
+        let batch: Vec<(K, V)> = map.iter().take(LEGACY_MIGRATION_BATCH_SIZE).collect();
+        if batch.is_empty() {
+            break;
+        }
+        for (key, value) in batch {
+            map.remove(&key);
+            f(key, value);
+        }
+    }
+}
+
+fn legacy_principal(principal: [u8; 29]) -> StorablePrincipal {
+    StorablePrincipal(Principal::from_slice(&principal))
+}
+
+/// Principals used to be stored truncated to 29 bytes, which only works for self-authenticating
+/// principals, and only a single address could be linked to a principal, with only its score
+/// stored. Moves the links to maps keyed by `StorablePrincipal`, converting the scores to score
+/// records and making each linked address the primary address of its principal. The legacy maps
+/// are emptied, so this is a no-op on subsequent upgrades.
+fn migrate_principal_keys() {
+    let mut legacy_scores = legacy_map::<[u8; 29], f32>(LEGACY_PRINCIPAL_SCORE_MEMORY_ID);
+    drain_legacy_map::<[u8; 32], [u8; 29]>(
+        LEGACY_ETH_PRINCIPAL_MEMORY_ID,
+        |address_hash, legacy_key| {
+            let principal = legacy_principal(legacy_key);
+            ETH_PRINCIPAL.with_borrow_mut(|e| e.insert(address_hash, principal));
+            if let Some(score) = legacy_scores.remove(&legacy_key) {
+                PRINCIPAL_ADDRESSES.with_borrow_mut(|a| {
+                    a.insert(
+                        (principal, address_hash),
+                        ScoreRecord::legacy(score, address_hash),
+                    )
+                });
+                PRINCIPAL_PRIMARY.with_borrow_mut(|p| p.insert(principal, address_hash));
+            }
+        },
+    );
+
+    // Scores of principals without a linked address cannot be migrated.
+    drop(legacy_scores);
+    drain_legacy_map::<[u8; 29], f32>(LEGACY_PRINCIPAL_SCORE_MEMORY_ID, |_, _| {});
 }
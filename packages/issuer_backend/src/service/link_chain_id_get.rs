@@ -0,0 +1,15 @@
+use crate::SETTINGS;
+use ic_cdk::query;
+
+/// Returns the chain ID that SIWE messages passed to `score_link` have to specify. Frontends put
+/// this in the message instead of the chain the wallet is connected to, so that wallets on any
+/// chain can link their address.
+#[query]
+pub fn link_chain_id_get() -> u64 {
+    SETTINGS.with_borrow(|settings_opt| {
+        settings_opt
+            .as_ref()
+            .expect("Settings should be initialized")
+            .siwe_chain_id
+    })
+}
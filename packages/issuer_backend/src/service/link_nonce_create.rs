@@ -0,0 +1,17 @@
+use crate::authenticated_guard::authenticated;
+use crate::nonce::create_nonce;
+use crate::rate_limit::check_link_rate_limit;
+use ic_cdk::{caller, update};
+
+/// Creates a single use nonce for the caller, to be included in the SIWE message passed to
+/// `score_link`. The nonce expires after a short period of time.
+///
+/// # Returns
+///
+/// * `Ok(String)` - The hex encoded nonce.
+/// * `Err(String)` - An error message if the nonce could not be generated.
+#[update(guard = authenticated)]
+pub async fn link_nonce_create() -> Result<String, String> {
+    check_link_rate_limit(&caller(), 1)?;
+    create_nonce(&caller()).await
+}
@@ -0,0 +1,67 @@
+use crate::address_hash::address_hash;
+use crate::authenticated_guard::authenticated;
+use crate::eth::create_siwe_transfer_statement;
+use crate::link::{transfer_links, verify_siwe_message};
+use crate::rate_limit::check_link_rate_limit;
+use crate::storable_principal::StorablePrincipal;
+use crate::{ETH_PRINCIPAL, PENDING_TRANSFERS};
+use candid::Principal;
+use ic_cdk::{api::time, caller, update};
+
+/// Completes a transfer of linked Ethereum addresses offered to the caller by `link_transfer_init`.
+///
+/// The caller proves that the transfer is wanted by the owner of the addresses with a fresh SIWE
+/// signature over the transfer statement, made by one of the addresses being transferred. The SIWE
+/// message must include a nonce issued to the caller by `link_nonce_create`.
+///
+/// # Arguments
+///
+/// * `from` - The principal that offered the transfer.
+/// * `message` - The SIWE message signed by one of the linked Ethereum addresses.
+/// * `signature` - The Ethereum signature of the message.
+///
+/// # Returns
+///
+/// * `Ok(())` - If the links were transferred to the caller.
+/// * `Err(String)` - An error message if any validation or operation fails.
+#[update(guard = authenticated)]
+pub async fn link_transfer_complete(
+    from: Principal,
+    message: String,
+    signature: String,
+) -> Result<(), String> {
+    check_link_rate_limit(&caller(), 1)?;
+
+    let caller_principal = StorablePrincipal(caller());
+    let from_principal = StorablePrincipal(from);
+
+    check_transfer_offer(&caller_principal, &from_principal)?;
+
+    // Verify the SIWE message and signature, consuming the nonce in the message.
+    let statement = create_siwe_transfer_statement(&from, &caller());
+    let siwe_message = verify_siwe_message(&message, &signature, &statement).await?;
+
+    // State may have changed while verifying the signature, check the transfer offer again.
+    check_transfer_offer(&caller_principal, &from_principal)?;
+
+    // The signing address has to be one of the addresses being transferred.
+    let address_hash = address_hash(&siwe_message.address)?;
+    if ETH_PRINCIPAL.with_borrow(|e| e.get(&address_hash)) != Some(from_principal) {
+        return Err("Signed address not linked to the transferring principal".to_string());
+    }
+
+    transfer_links(&from_principal, &caller_principal)
+}
+
+/// Makes sure a transfer from one principal to another has been offered and has not expired.
+/// Expired offers are removed.
+fn check_transfer_offer(to: &StorablePrincipal, from: &StorablePrincipal) -> Result<(), String> {
+    let expires_at = PENDING_TRANSFERS
+        .with_borrow(|t| t.get(&(*to, *from)))
+        .ok_or_else(|| "No pending transfer".to_string())?;
+    if expires_at <= time() {
+        PENDING_TRANSFERS.with_borrow_mut(|t| t.remove(&(*to, *from)));
+        return Err("Transfer expired".to_string());
+    }
+    Ok(())
+}
@@ -0,0 +1,48 @@
+use crate::authenticated_guard::authenticated;
+use crate::link::linked_address_count;
+use crate::storable_principal::StorablePrincipal;
+use crate::PENDING_TRANSFERS;
+use candid::Principal;
+use ic_cdk::{api::time, caller, update};
+
+/// How long a transfer offer can be completed before it expires.
+const LINK_TRANSFER_TTL_NS: u64 = 24 * 60 * 60 * 1_000_000_000; // 24 hours
+
+/// Offers to transfer all Ethereum addresses linked to the caller, and their scores, to another
+/// principal. This is the first step of a two step flow, the transfer is completed by the new
+/// principal calling `link_transfer_complete`.
+///
+/// Calling this function again replaces any previous offer made by the caller to the same
+/// principal. Offers made to the same principal by others are not affected.
+///
+/// # Arguments
+///
+/// * `to` - The principal to transfer the links to.
+///
+/// # Returns
+///
+/// * `Ok(())` - If the transfer offer was registered.
+/// * `Err(String)` - An error message if the caller has nothing to transfer.
+#[update(guard = authenticated)]
+pub fn link_transfer_init(to: Principal) -> Result<(), String> {
+    let caller_principal = StorablePrincipal(caller());
+
+    let to_principal = StorablePrincipal(to);
+
+    if to_principal == caller_principal {
+        return Err("Cannot transfer to the same principal".to_string());
+    }
+
+    if linked_address_count(&caller_principal) == 0 {
+        return Err("No linked addresses to transfer".to_string());
+    }
+
+    PENDING_TRANSFERS.with_borrow_mut(|t| {
+        t.insert(
+            (to_principal, caller_principal),
+            time() + LINK_TRANSFER_TTL_NS,
+        );
+    });
+
+    Ok(())
+}
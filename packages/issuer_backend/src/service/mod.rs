@@ -1,7 +1,16 @@
 mod http_request;
 mod init_upgrade;
+mod link_chain_id_get;
+mod link_nonce_create;
+mod link_transfer_complete;
+mod link_transfer_init;
+mod outcall_metrics_get;
 mod score_get;
 mod score_link;
+mod score_link_typed;
+mod score_primary_set;
+mod score_unlink;
+mod settings_update;
 mod transform;
 mod vc_consent_message;
 mod vc_derivation_origin;
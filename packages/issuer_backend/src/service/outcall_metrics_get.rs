@@ -0,0 +1,11 @@
+use crate::controller_guard::controller;
+use crate::outcall::OutcallMetrics;
+use crate::OUTCALL_METRICS;
+use ic_cdk::query;
+
+/// Returns the cycles spent on HTTPS outcalls, per kind of outcall. Only controllers of the canister
+/// are allowed to call this function.
+#[query(guard = controller)]
+pub fn outcall_metrics_get() -> Vec<(String, OutcallMetrics)> {
+    OUTCALL_METRICS.with_borrow(|metrics| metrics.iter().collect())
+}
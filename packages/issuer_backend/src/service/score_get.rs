@@ -1,16 +1,21 @@
 use crate::authenticated_guard::authenticated;
+use crate::link::{effective_score, linked_address_count, ScoreInfo};
 use ic_cdk::{caller, query};
 
-use crate::PRINCIPAL_SCORE;
+use crate::storable_principal::StorablePrincipal;
 
+/// Returns the effective score of the caller together with the number of linked addresses.
 #[query(guard = authenticated)]
-pub fn score_get() -> Result<f32, String> {
-    let caller_principal: [u8; 29] = caller().as_slice()[..29]
-        .try_into()
-        .map_err(|_| "Invalid principal".to_string())?;
+pub fn score_get() -> Result<ScoreInfo, String> {
+    let caller_principal = StorablePrincipal(caller());
 
-    PRINCIPAL_SCORE.with_borrow(|s| match s.get(&caller_principal) {
-        Some(score) => Ok(score),
+    match effective_score(&caller_principal) {
+        Some(record) => Ok(ScoreInfo {
+            score: record.score,
+            linked_addresses: linked_address_count(&caller_principal),
+            fetched_at: record.fetched_at,
+            expires_at: record.expires_at,
+        }),
         None => Err("Principal not found".to_string()),
-    })
+    }
 }
@@ -0,0 +1,55 @@
+use crate::authenticated_guard::authenticated;
+use crate::eth::{EthAddress, LinkIdentity};
+use crate::link::{link_address, verify_signature, ScoreLinkError};
+use crate::nonce::consume_nonce;
+use crate::rate_limit::check_link_rate_limit;
+use ic_cdk::{api::time, caller, update};
+
+/// Links an Ethereum address to a principal and a passport score, using an EIP-712 typed data
+/// signature as proof of address ownership. This is an alternative to `score_link` for wallets that
+/// present typed data more clearly than plain text messages.
+///
+/// The signed typed data is a `LinkIdentity` struct, see `eth::LinkIdentity` for the exact
+/// definition and domain.
+///
+/// # Arguments
+///
+/// * `wallet` - The Ethereum address to be registered or refreshed.
+/// * `nonce` - The nonce issued by `link_nonce_create`.
+/// * `expiry` - The expiry of the signature in seconds since the Unix epoch.
+/// * `signature` - The Ethereum signature of the typed data.
+///
+/// # Returns
+///
+/// * `Ok(f32)` - The passport score if registration or refresh is successful.
+/// * `Err(ScoreLinkError)` - An error if fetching the score or any validation or operation fails.
+///   If the score is still being processed by the Passport API, `Processing` is returned and the
+///   address is linked in the background once the score is available.
+#[update(guard = authenticated)]
+pub async fn score_link_typed(
+    wallet: String,
+    nonce: String,
+    expiry: u64,
+    signature: String,
+) -> Result<f32, ScoreLinkError> {
+    // Each link attempt can result in paid HTTPS outcalls, limit the number of attempts.
+    check_link_rate_limit(&caller(), 1)?;
+
+    let link_identity = LinkIdentity {
+        wallet: EthAddress::new(&wallet)?,
+        principal: caller(),
+        nonce,
+        expiry,
+    };
+    link_identity.validate(time())?;
+
+    // Make sure the signature cannot be used again, also by calls made while it is verified.
+    let typed_data_hash = link_identity.eip712_hash()?;
+    consume_nonce(&caller(), &link_identity.nonce)?;
+
+    // Verify that the typed data was signed by the wallet address.
+    verify_signature(&link_identity.wallet, &typed_data_hash, &signature).await?;
+
+    // Fetch the passport score and register or update the principal and address.
+    link_address(&caller(), &link_identity.wallet).await
+}
@@ -0,0 +1,38 @@
+use crate::address_hash::address_hash;
+use crate::authenticated_guard::authenticated;
+use crate::eth::EthAddress;
+use crate::link::effective_score;
+use crate::storable_principal::StorablePrincipal;
+use crate::{PRINCIPAL_ADDRESSES, PRINCIPAL_PRIMARY};
+use ic_cdk::{caller, update};
+
+/// Designates one of the Ethereum addresses linked to the caller as the primary address. When the
+/// score policy is `Primary`, the score of this address is the effective score of the caller.
+///
+/// # Arguments
+///
+/// * `address` - A linked Ethereum address.
+///
+/// # Returns
+///
+/// * `Ok(f32)` - The effective score of the caller after the change.
+/// * `Err(String)` - An error message if the address is not linked to the caller.
+#[update(guard = authenticated)]
+pub fn score_primary_set(address: String) -> Result<f32, String> {
+    let caller_principal = StorablePrincipal(caller());
+
+    let address = EthAddress::new(&address)?;
+    let address_hash = address_hash(&address)?;
+
+    if !PRINCIPAL_ADDRESSES.with_borrow(|a| a.contains_key(&(caller_principal, address_hash))) {
+        return Err("Address not linked to principal".to_string());
+    }
+
+    PRINCIPAL_PRIMARY.with_borrow_mut(|p| {
+        p.insert(caller_principal, address_hash);
+    });
+
+    effective_score(&caller_principal)
+        .map(|record| record.score)
+        .ok_or_else(|| "Principal not found".to_string())
+}
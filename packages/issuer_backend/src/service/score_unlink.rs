@@ -0,0 +1,58 @@
+use crate::address_hash::address_hash;
+use crate::authenticated_guard::authenticated;
+use crate::eth::{create_siwe_unlink_statement, EthAddress};
+use crate::link::{unlink_address, verify_siwe_message, UnlinkProof};
+use crate::rate_limit::check_link_rate_limit;
+use crate::settings::UnlinkAuthorization;
+use crate::storable_principal::StorablePrincipal;
+use crate::SETTINGS;
+use ic_cdk::{caller, update};
+
+/// Unlinks an Ethereum address from the caller and erases the score of the address. Once the last
+/// address is unlinked, no score is stored for the caller anymore.
+///
+/// Depending on the configured unlink authorization, the caller either only needs to be the
+/// principal the address is linked to, or also has to provide a fresh SIWE signature by the
+/// address. The SIWE message must include a nonce issued by `link_nonce_create`.
+///
+/// # Arguments
+///
+/// * `address` - The Ethereum address to unlink.
+/// * `proof` - A signed SIWE message, required if the unlink authorization is `EthSignature`.
+///
+/// # Returns
+///
+/// * `Ok(())` - If the address was unlinked.
+/// * `Err(String)` - An error message if any validation or operation fails.
+#[update(guard = authenticated)]
+pub async fn score_unlink(address: String, proof: Option<UnlinkProof>) -> Result<(), String> {
+    check_link_rate_limit(&caller(), 1)?;
+
+    let caller_principal = StorablePrincipal(caller());
+
+    let address = EthAddress::new(&address)?;
+
+    let unlink_authorization = SETTINGS.with_borrow(|settings_opt| {
+        settings_opt
+            .as_ref()
+            .expect("Settings should be initialized")
+            .unlink_authorization
+    });
+
+    match (unlink_authorization, proof) {
+        (_, Some(proof)) => {
+            let statement = create_siwe_unlink_statement(&caller());
+            let siwe_message =
+                verify_siwe_message(&proof.message, &proof.signature, &statement).await?;
+            if siwe_message.address.as_str() != address.as_str() {
+                return Err("Signed address does not match address to unlink".to_string());
+            }
+        }
+        (UnlinkAuthorization::EthSignature, None) => {
+            return Err("Unlinking requires a signature".to_string());
+        }
+        (UnlinkAuthorization::Principal, None) => {}
+    }
+
+    unlink_address(&caller_principal, &address_hash(&address)?)
+}
@@ -0,0 +1,98 @@
+use crate::certified_data::init_assets;
+use crate::controller_guard::controller;
+use crate::settings::{persist_settings, secs_to_ns, SettingsUpdate};
+use crate::vc::normalize_origin;
+use crate::SETTINGS;
+use ic_cdk::update;
+
+fn non_empty(value: String) -> Option<String> {
+    if value.is_empty() {
+        None
+    } else {
+        Some(value)
+    }
+}
+
+/// Updates settings at runtime, without the need to reinstall or upgrade the canister. Only
+/// controllers of the canister are allowed to call this function.
+///
+/// # Arguments
+///
+/// * `update` - The settings to update, fields that are not set are left unchanged. Nothing is
+///   updated if any of the fields is invalid.
+#[update(guard = controller)]
+pub fn settings_update(update: SettingsUpdate) -> Result<(), String> {
+    let frontend_hostnames_updated = update.frontend_hostnames.is_some();
+
+    let mut settings = SETTINGS.with_borrow(|settings_opt| {
+        settings_opt
+            .as_ref()
+            .expect("Settings should be initialized")
+            .clone()
+    });
+
+    if let Some(passport_api_url) = update.passport_api_url {
+        settings.passport_api_url = passport_api_url;
+    }
+    if let Some(passport_scorer_id) = update.passport_scorer_id {
+        settings.passport_scorer_id = non_empty(passport_scorer_id);
+    }
+    if let Some(passport_api_key) = update.passport_api_key {
+        settings.passport_api_key = non_empty(passport_api_key);
+    }
+    if let Some(attempts) = update.principal_link_attempts {
+        settings.principal_link_attempts = attempts;
+    }
+    if let Some(window_secs) = update.principal_link_window_secs {
+        settings.principal_link_window_ns = secs_to_ns(window_secs)?;
+    }
+    if let Some(capacity) = update.global_link_bucket_capacity {
+        settings.global_link_bucket_capacity = capacity;
+    }
+    if let Some(refill_interval_secs) = update.global_link_refill_interval_secs {
+        settings.global_link_refill_interval_ns = secs_to_ns(refill_interval_secs)?;
+    }
+    if let Some(max_age_secs) = update.score_refresh_max_age_secs {
+        settings.score_refresh_max_age_ns = (max_age_secs > 0)
+            .then(|| secs_to_ns(max_age_secs))
+            .transpose()?;
+    }
+    if let Some(batch_size) = update.score_refresh_batch_size {
+        settings.score_refresh_batch_size = batch_size;
+    }
+    if let Some(cycles_budget) = update.score_refresh_cycles_budget {
+        settings.score_refresh_cycles_budget = cycles_budget.into();
+    }
+    if let Some(max_age_secs) = update.credential_max_score_age_secs {
+        settings.credential_max_score_age_ns = (max_age_secs > 0)
+            .then(|| secs_to_ns(max_age_secs))
+            .transpose()?;
+    }
+    if let Some(derivation_origin) = update.derivation_origin {
+        settings.derivation_origin = normalize_origin(&derivation_origin);
+    }
+    if let Some(frontend_hostnames) = update.frontend_hostnames {
+        settings.frontend_hostnames = frontend_hostnames
+            .iter()
+            .map(|hostname| normalize_origin(hostname))
+            .collect();
+    }
+    if let Some(min_validity_secs) = update.credential_min_validity_secs {
+        settings.credential_min_validity_ns = secs_to_ns(min_validity_secs)?;
+    }
+    if let Some(max_validity_secs) = update.credential_max_validity_secs {
+        settings.credential_max_validity_ns = secs_to_ns(max_validity_secs)?;
+    }
+
+    settings.validate()?;
+
+    SETTINGS.with_borrow_mut(|settings_opt| *settings_opt = Some(settings));
+    persist_settings();
+
+    // The alternative origins asset is generated from the frontend hostnames.
+    if frontend_hostnames_updated {
+        init_assets();
+    }
+
+    Ok(())
+}
@@ -2,7 +2,21 @@ use candid::Nat;
 use ic_cdk::update;
 use vc_util::issuer_api::{Icrc21ConsentInfo, Icrc21Error, Icrc21VcConsentMessageRequest};
 
-use crate::vc::{get_credential_min_score, validate_credential_spec};
+use crate::vc::{get_credential_min_score, get_credential_validity_ns, validate_credential_spec};
+
+/// Formats a validity period for display, in the largest unit that divides it evenly.
+fn format_validity(validity_ns: u64) -> String {
+    let secs = validity_ns / 1_000_000_000;
+    let (value, unit) = if secs % (60 * 60) == 0 {
+        (secs / (60 * 60), "hour")
+    } else if secs % 60 == 0 {
+        (secs / 60, "minute")
+    } else {
+        (secs, "second")
+    };
+    let plural = if value == 1 { "" } else { "s" };
+    format!("{value} {unit}{plural}")
+}
 
 /// Handles the generation of a consent message for credential sharing.
 ///
@@ -42,8 +56,17 @@ async fn vc_consent_message(
             description: "minScore not found in credential type".to_string(),
         })?;
 
+    // Retrieve the validity period of the credential.
+    let validity = get_credential_validity_ns(&req.credential_spec).map_err(|_| {
+        Icrc21Error::GenericError {
+            error_code: Nat::from(400u32),
+            description: "Invalid validitySeconds in credential type".to_string(),
+        }
+    })?;
+    let validity = format_validity(validity);
+
     // Construct the consent message.
-    let consent_message = format!("<h1>Gitcoin Passport Score</h1><br/>Minimum Score: {min_score}<br/><br/>Sharing the credential DOES NOT mean revealing your exact Passport Score, Ethereum address or other personal information.");
+    let consent_message = format!("<h1>Gitcoin Passport Score</h1><br/>Minimum Score: {min_score}<br/>Valid for: {validity}<br/><br/>Sharing the credential DOES NOT mean revealing your exact Passport Score, Ethereum address or other personal information.");
 
     Ok(Icrc21ConsentInfo {
         consent_message,
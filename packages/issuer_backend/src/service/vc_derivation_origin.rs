@@ -1,19 +1,13 @@
+use crate::vc::resolve_derivation_origin;
 use ic_cdk::update;
 use vc_util::issuer_api::{DerivationOriginData, DerivationOriginError, DerivationOriginRequest};
 
+/// Returns the origin that credentials are derived for, given the hostname of the frontend
+/// requesting them. Fails with `UnsupportedOrigin` for hostnames that are not configured in settings.
 #[update(name = "derivation_origin")]
 async fn vc_derivation_origin(
-    _req: DerivationOriginRequest,
+    req: DerivationOriginRequest,
 ) -> Result<DerivationOriginData, DerivationOriginError> {
-    let dfx_network = option_env!("DFX_NETWORK").unwrap();
-    let origin = match dfx_network {
-        "local" => format!("http://{}.localhost:4943", ic_cdk::api::id()),
-        "ic" => format!("https://{}.icp0.io", ic_cdk::api::id()),
-        _ => {
-            return Err(DerivationOriginError::Internal(
-                "Invalid DFX_NETWORK".to_string(),
-            ))
-        }
-    };
+    let origin = resolve_derivation_origin(&req.frontend_hostname)?;
     Ok(DerivationOriginData { origin })
 }
@@ -1,5 +1,6 @@
 use crate::vc::{
-    create_credential_hash, create_jws, get_alias_tuple, get_signature, validate_credential_spec,
+    create_credential_hash, create_jws, credential_salt, get_alias_tuple, get_signature,
+    validate_credential_spec,
 };
 use ic_cdk::{api::time, caller, query};
 use vc_util::issuer_api::{GetCredentialRequest, IssueCredentialError, IssuedCredentialData};
@@ -33,11 +34,13 @@ fn vc_get_credential(
     let credential_jwt = String::from_utf8(prepared_context.into_vec())
         .map_err(|_| IssueCredentialError::Internal("Invalid prepared_context".to_string()))?;
 
-    let credential_hash = create_credential_hash(&alias_tuple, &credential_jwt)?;
+    let salt = credential_salt()?;
 
-    let sig = get_signature(&alias_tuple, credential_hash)?;
+    let credential_hash = create_credential_hash(&salt, &alias_tuple, &credential_jwt)?;
 
-    let vc_jws = create_jws(&alias_tuple, &credential_jwt, sig.as_slice())?;
+    let sig = get_signature(&salt, &alias_tuple, credential_hash)?;
+
+    let vc_jws = create_jws(&salt, &alias_tuple, &credential_jwt, sig.as_slice())?;
 
     Ok(IssuedCredentialData { vc_jws })
 }
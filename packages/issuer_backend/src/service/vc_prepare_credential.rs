@@ -1,9 +1,10 @@
 use crate::{
+    storable_principal::StorablePrincipal,
     vc::{
-        create_credential_hash, create_credential_jwt, get_alias_tuple, save_credential_hash,
-        validate_credential_spec, validate_score,
+        create_credential_hash, create_credential_id, create_credential_jwt, credential_salt,
+        credential_score, get_alias_tuple, save_credential_hash, validate_credential_spec,
+        validate_score,
     },
-    PRINCIPAL_SCORE,
 };
 use ic_cdk::{api::time, caller, update};
 use serde_bytes::ByteBuf;
@@ -30,21 +31,21 @@ async fn vc_prepare_credential(
     let alias_tuple = get_alias_tuple(&req.signed_id_alias, &caller(), time().into())?;
     validate_credential_spec(&req.credential_spec)?;
 
-    let principal: [u8; 29] = alias_tuple.id_dapp.as_slice()[..29].try_into().unwrap();
-    let score = PRINCIPAL_SCORE.with_borrow(|s| match s.get(&principal) {
-        Some(score) => Ok(score),
-        None => Err(IssueCredentialError::UnauthorizedSubject(
-            "No score registered for principal".to_string(),
-        )),
-    })?;
+    let principal = StorablePrincipal(alias_tuple.id_dapp);
+    let record = credential_score(&principal)?;
 
-    validate_score(score, &req.credential_spec)?;
+    validate_score(record.score, &req.credential_spec)?;
 
-    let credential_jwt = create_credential_jwt(&req.credential_spec, &alias_tuple)?;
+    // Only ask for randomness once the principal is known to qualify for the credential.
+    let credential_id = create_credential_id().await?;
 
-    let credential_hash = create_credential_hash(&alias_tuple, &credential_jwt)?;
+    let credential_jwt = create_credential_jwt(&req.credential_spec, &alias_tuple, &credential_id)?;
 
-    save_credential_hash(&alias_tuple, credential_hash)?;
+    let salt = credential_salt()?;
+
+    let credential_hash = create_credential_hash(&salt, &alias_tuple, &credential_jwt)?;
+
+    save_credential_hash(&salt, &alias_tuple, credential_hash)?;
 
     Ok(PreparedCredentialData {
         prepared_context: Some(ByteBuf::from(credential_jwt.as_bytes())),
@@ -1,13 +1,234 @@
-use candid::{CandidType, Principal};
+use crate::score_provider::ScoreProviderKind;
+use crate::{SETTINGS, STORED_SETTINGS};
+use candid::{CandidType, Decode, Encode, Principal};
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::Storable;
 use serde::Deserialize;
+use std::borrow::Cow;
+
+pub const DEFAULT_MAX_LINKED_ADDRESSES: u32 = 5;
+
+// Ethereum mainnet
+pub const DEFAULT_SIWE_CHAIN_ID: u64 = 1;
+
+pub const DEFAULT_PRINCIPAL_LINK_ATTEMPTS: u32 = 10;
+pub const DEFAULT_PRINCIPAL_LINK_WINDOW_SECS: u64 = 60 * 60;
+pub const DEFAULT_GLOBAL_LINK_BUCKET_CAPACITY: u64 = 100;
+pub const DEFAULT_GLOBAL_LINK_REFILL_INTERVAL_SECS: u64 = 10;
+
+// Application subnets have 13 nodes
+pub const DEFAULT_SUBNET_SIZE: u32 = 13;
+
+pub const DEFAULT_SCORE_REFRESH_BATCH_SIZE: u32 = 5;
+pub const DEFAULT_SCORE_REFRESH_CYCLES_BUDGET: u64 = 1_000_000_000;
+
+pub const DEFAULT_CREDENTIAL_MIN_VALIDITY_SECS: u64 = 60;
+pub const DEFAULT_CREDENTIAL_MAX_VALIDITY_SECS: u64 = 24 * 60 * 60;
+
+// Since the Gitcoin Passport API does not accept IPv6 connections, requests are proxied through a
+// Cloudflare Worker. Source code for the Worker can be found at
+// https://github.com/kristoferlund/passport-score-api-proxy
+pub const DEFAULT_PASSPORT_API_URL: &str = "https://passport-score-proxy.kristofer-977.workers.dev";
+
+/// Determines how the effective score of a principal is computed when multiple Ethereum addresses
+/// are linked to it.
+#[derive(CandidType, Deserialize, Debug, Clone, Copy, Default)]
+pub enum ScorePolicy {
+    /// The highest score of all linked addresses.
+    #[default]
+    Max,
+    /// The score of the primary address.
+    Primary,
+}
+
+/// Determines what a principal needs to provide to unlink an Ethereum address.
+#[derive(CandidType, Deserialize, Debug, Clone, Copy, Default)]
+pub enum UnlinkAuthorization {
+    /// Being authenticated as the principal the address is linked to is enough.
+    #[default]
+    Principal,
+    /// A fresh SIWE signature by the address is required as well.
+    EthSignature,
+}
 
 #[derive(CandidType, Deserialize, Debug, Clone)]
 pub struct SettingsInput {
     pub ic_root_key_der: Vec<u8>,
     pub ii_canister_id: Principal,
+    pub siwe_domain: String,
+    pub siwe_uri: String,
+    pub siwe_chain_id: Option<u64>,
+    pub eth_rpc_url: Option<String>,
+    pub max_linked_addresses: Option<u32>,
+    pub score_policy: Option<ScorePolicy>,
+    pub unlink_authorization: Option<UnlinkAuthorization>,
+    pub unlink_cooldown_secs: Option<u64>,
+    pub passport_api_url: Option<String>,
+    pub passport_scorer_id: Option<String>,
+    pub passport_api_key: Option<String>,
+    pub score_provider: Option<ScoreProviderKind>,
+    pub free_outcalls_per_principal: Option<u64>,
+    pub subnet_size: Option<u32>,
+    pub principal_link_attempts: Option<u32>,
+    pub principal_link_window_secs: Option<u64>,
+    pub global_link_bucket_capacity: Option<u64>,
+    pub global_link_refill_interval_secs: Option<u64>,
+    pub score_refresh_max_age_secs: Option<u64>,
+    pub score_refresh_batch_size: Option<u32>,
+    pub score_refresh_cycles_budget: Option<u64>,
+    pub credential_max_score_age_secs: Option<u64>,
+    /// URL identifying the issuer in issued credentials. Defaults to the URL of the canister on
+    /// mainnet, `https://<canister id>.icp0.io`. Local deployments have to set it, for instance to
+    /// `http://<canister id>.localhost:4943` as done by the Makefile.
+    pub issuer_url: Option<String>,
+    /// Canonical origin that credentials are derived for. Has the same default as `issuer_url`,
+    /// and local deployments have to set it as well.
+    pub derivation_origin: Option<String>,
+    pub frontend_hostnames: Option<Vec<String>>,
+    pub credential_min_validity_secs: Option<u64>,
+    pub credential_max_validity_secs: Option<u64>,
 }
 
+#[derive(CandidType, Deserialize, Debug, Clone)]
 pub struct Settings {
     pub ic_root_key_raw: Vec<u8>,
     pub ii_canister_id: Principal,
+    pub siwe_domain: String,
+    pub siwe_uri: String,
+    pub siwe_chain_id: u64,
+    pub eth_rpc_url: Option<String>,
+    pub max_linked_addresses: u32,
+    pub score_policy: ScorePolicy,
+    pub unlink_authorization: UnlinkAuthorization,
+    pub unlink_cooldown_ns: u64,
+    pub passport_api_url: String,
+    pub passport_scorer_id: Option<String>,
+    pub passport_api_key: Option<String>,
+    pub score_provider: ScoreProviderKind,
+    pub free_outcalls_per_principal: Option<u64>,
+    /// Number of nodes in the subnet the canister is deployed to, used to compute the cost of
+    /// HTTPS outcalls.
+    pub subnet_size: u32,
+    pub principal_link_attempts: u32,
+    pub principal_link_window_ns: u64,
+    pub global_link_bucket_capacity: u64,
+    pub global_link_refill_interval_ns: u64,
+    pub score_refresh_max_age_ns: Option<u64>,
+    pub score_refresh_batch_size: u32,
+    pub score_refresh_cycles_budget: u128,
+    pub credential_max_score_age_ns: Option<u64>,
+    /// URL identifying the issuer in issued credentials.
+    pub issuer_url: String,
+    /// Canonical origin that credentials are derived for, see `vc::resolve_derivation_origin`.
+    pub derivation_origin: String,
+    /// Origins of frontends, besides the derivation origin, that are allowed to request
+    /// credentials. All of them map to the derivation origin, and are served as Internet Identity
+    /// alternative origins.
+    pub frontend_hostnames: Vec<String>,
+    /// Bounds for the validity period of issued credentials, see `vc::get_credential_validity_ns`.
+    pub credential_min_validity_ns: u64,
+    pub credential_max_validity_ns: u64,
+}
+
+impl Settings {
+    /// Checks that the settings are consistent.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.credential_min_validity_ns > self.credential_max_validity_ns {
+            return Err(
+                "Minimum credential validity cannot exceed the maximum credential validity"
+                    .to_string(),
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Converts a duration in seconds, as passed in settings, to nanoseconds. Fails if the duration
+/// does not fit in nanoseconds.
+pub fn secs_to_ns(secs: u64) -> Result<u64, String> {
+    secs.checked_mul(1_000_000_000)
+        .ok_or_else(|| format!("Duration of {} seconds is too long", secs))
+}
+
+/// Settings that controllers can update at runtime using `settings_update`. Fields that are not
+/// set are left unchanged. Updates are kept across upgrades, unless the field is set in the
+/// arguments of the upgrade.
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct SettingsUpdate {
+    /// Base URL of the Passport API or a proxy in front of it.
+    pub passport_api_url: Option<String>,
+    /// The Passport scorer to use. An empty string removes the scorer id.
+    pub passport_scorer_id: Option<String>,
+    /// API key sent in the `X-API-KEY` header. An empty string removes the API key.
+    pub passport_api_key: Option<String>,
+    /// Number of link attempts each principal can make per window.
+    pub principal_link_attempts: Option<u32>,
+    /// Length of the window for link attempts by a principal, in seconds.
+    pub principal_link_window_secs: Option<u64>,
+    /// Maximum number of link attempts that can be made by all principals together in a burst.
+    pub global_link_bucket_capacity: Option<u64>,
+    /// Seconds between each link attempt added back to the global bucket.
+    pub global_link_refill_interval_secs: Option<u64>,
+    /// Scores older than this are refreshed in the background, in seconds. Zero disables
+    /// background refreshes.
+    pub score_refresh_max_age_secs: Option<u64>,
+    /// Maximum number of scores refreshed per run.
+    pub score_refresh_batch_size: Option<u32>,
+    /// Maximum number of cycles spent on outcalls per run.
+    pub score_refresh_cycles_budget: Option<u64>,
+    /// Credentials are not issued for scores fetched longer ago than this, in seconds. Zero
+    /// disables the check.
+    pub credential_max_score_age_secs: Option<u64>,
+    /// Canonical origin that credentials are derived for.
+    pub derivation_origin: Option<String>,
+    /// Origins of frontends, besides the derivation origin, that are allowed to request
+    /// credentials. Replaces the current list.
+    pub frontend_hostnames: Option<Vec<String>>,
+    /// Minimum validity period of issued credentials, in seconds.
+    pub credential_min_validity_secs: Option<u64>,
+    /// Maximum validity period of issued credentials, in seconds.
+    pub credential_max_validity_secs: Option<u64>,
+}
+
+/// Versions of the settings, making it possible to change the settings without migrating stable
+/// memory. New versions are added as variants, older versions are converted when read.
+#[derive(CandidType, Deserialize)]
+enum VersionedSettings {
+    V1(Settings),
+}
+
+/// The settings as persisted in stable memory, `None` until the canister has been initialized.
+#[derive(Default)]
+pub struct StoredSettings(pub Option<Settings>);
+
+impl Storable for StoredSettings {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        let versioned = self.0.clone().map(VersionedSettings::V1);
+        Cow::Owned(Encode!(&versioned).expect("Failed to encode settings"))
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        match Decode!(&bytes, Option<VersionedSettings>).expect("Failed to decode settings") {
+            Some(VersionedSettings::V1(settings)) => StoredSettings(Some(settings)),
+            None => StoredSettings(None),
+        }
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+/// Returns the settings persisted in stable memory, if any.
+pub fn stored_settings() -> Option<Settings> {
+    STORED_SETTINGS.with_borrow(|stored| stored.get().0.clone())
+}
+
+/// Persists the current settings to stable memory, so that they are kept across upgrades. Called
+/// whenever the settings change.
+pub fn persist_settings() {
+    let settings = SETTINGS.with_borrow(|settings_opt| settings_opt.clone());
+    STORED_SETTINGS.with_borrow_mut(|stored| {
+        stored
+            .set(StoredSettings(settings))
+            .expect("Failed to persist settings")
+    });
 }
@@ -0,0 +1,30 @@
+use candid::Principal;
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::Storable;
+use std::borrow::Cow;
+
+/// The maximum length of a principal in bytes, see the
+/// [IC interface specification](https://internetcomputer.org/docs/current/references/ic-interface-spec#principal).
+const PRINCIPAL_MAX_LENGTH_IN_BYTES: u32 = 29;
+
+/// A principal that can be stored as a key or value in stable structures.
+///
+/// Principals have a variable length of at most 29 bytes. Self-authenticating principals, such as
+/// the ones created by Internet Identity, are 29 bytes long while canister ids are shorter.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct StorablePrincipal(pub Principal);
+
+impl Storable for StorablePrincipal {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Borrowed(self.0.as_slice())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        StorablePrincipal(Principal::from_slice(&bytes))
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: PRINCIPAL_MAX_LENGTH_IN_BYTES,
+        is_fixed_size: false,
+    };
+}
@@ -1,7 +1,14 @@
-use crate::{certified_data::update_root_hash, ASSETS, CREDENTIAL_TYPE, SETTINGS, SIGNATURES};
+use crate::{
+    certified_data::update_root_hash,
+    link::{effective_score, effective_score_of},
+    random::init_random_secret,
+    score_record::ScoreRecord,
+    storable_principal::StorablePrincipal,
+    ASSETS, CREDENTIAL_SALT, CREDENTIAL_TYPE, SETTINGS, SIGNATURES,
+};
 use candid::Principal;
 use canister_sig_util::{hash_bytes, CanisterSigPublicKey};
-use ic_cdk::api::time;
+use ic_cdk::api::{management_canister::main::raw_rand, time};
 use ic_certification::Hash;
 use identity_core::{
     common::{Timestamp, Url},
@@ -11,15 +18,45 @@ use identity_credential::credential::{Credential, CredentialBuilder, Subject};
 use serde_json::json;
 use vc_util::{
     did_for_principal, get_verified_id_alias_from_jws,
-    issuer_api::{ArgumentValue, CredentialSpec, IssueCredentialError, SignedIdAlias},
+    issuer_api::{
+        ArgumentValue, CredentialSpec, DerivationOriginError, IssueCredentialError, SignedIdAlias,
+    },
     vc_jwt_to_jws, vc_signing_input, vc_signing_input_hash, AliasTuple,
 };
 
+/// Validity period of credentials that do not specify `validitySeconds`.
 const VC_EXPIRATION_PERIOD_NS: u64 = 15 * 60 * 1_000_000_000; // 15 minutes
 
+/// Normalizes an origin for comparison, ignoring case and trailing slashes.
+pub fn normalize_origin(origin: &str) -> String {
+    origin.trim_end_matches('/').to_lowercase()
+}
+
+/// Maps the hostname of the frontend requesting a credential to the canonical derivation origin.
+///
+/// The derivation origin itself and the frontend hostnames allowed in settings all map to the
+/// derivation origin, making the same principals available across custom domains. Other hostnames
+/// are rejected.
+pub fn resolve_derivation_origin(frontend_hostname: &str) -> Result<String, DerivationOriginError> {
+    let frontend_hostname = normalize_origin(frontend_hostname);
+    SETTINGS.with_borrow(|settings_opt| {
+        let settings = settings_opt
+            .as_ref()
+            .expect("Settings should be initialized");
+        if frontend_hostname == settings.derivation_origin
+            || settings.frontend_hostnames.contains(&frontend_hostname)
+        {
+            Ok(settings.derivation_origin.clone())
+        } else {
+            Err(DerivationOriginError::UnsupportedOrigin(frontend_hostname))
+        }
+    })
+}
+
 /// Validates that the provided credential specification matches the expected type and contains a valid argument.
 ///
 /// The function ensures that the credential type is supported and the minimum score argument is present and positive.
+/// The optional validity argument has to be positive, other arguments are not supported.
 pub fn validate_credential_spec(
     credential_spec: &CredentialSpec,
 ) -> Result<(), IssueCredentialError> {
@@ -30,9 +67,15 @@ pub fn validate_credential_spec(
         ));
     }
 
-    // Validate the presence and value of the 'minScore' argument
+    // Validate the presence and value of the 'minScore' argument, and the value of the optional
+    // 'validitySeconds' argument
     if let Some(arguments) = &credential_spec.arguments {
-        if arguments.len() == 1 {
+        let (expected_len, valid_validity) = match arguments.get("validitySeconds") {
+            None => (1, true),
+            Some(ArgumentValue::Int(validity_secs)) => (2, *validity_secs > 0),
+            Some(_) => (2, false),
+        };
+        if arguments.len() == expected_len && valid_validity {
             if let Some(ArgumentValue::Int(min_score)) = arguments.get("minScore") {
                 if *min_score > 0 {
                     return Ok(());
@@ -62,6 +105,39 @@ pub fn get_credential_min_score(
     ))
 }
 
+/// Retrieves the validity period of the credential, in nanoseconds.
+///
+/// The period is taken from the optional 'validitySeconds' argument, defaulting to 15 minutes, and
+/// clamped to the bounds configured in settings.
+pub fn get_credential_validity_ns(
+    credential_spec: &CredentialSpec,
+) -> Result<u64, IssueCredentialError> {
+    let requested_ns = match credential_spec
+        .arguments
+        .as_ref()
+        .and_then(|arguments| arguments.get("validitySeconds"))
+    {
+        None => VC_EXPIRATION_PERIOD_NS,
+        Some(ArgumentValue::Int(validity_secs)) if *validity_secs > 0 => {
+            *validity_secs as u64 * 1_000_000_000
+        }
+        Some(_) => {
+            return Err(IssueCredentialError::UnsupportedCredentialSpec(
+                "Invalid validitySeconds".to_string(),
+            ))
+        }
+    };
+
+    SETTINGS.with_borrow(|settings_opt| {
+        let settings = settings_opt
+            .as_ref()
+            .expect("Settings should be initialized");
+        Ok(requested_ns
+            .max(settings.credential_min_validity_ns)
+            .min(settings.credential_max_validity_ns))
+    })
+}
+
 /// Verifies the ID alias from the signed JWT and returns the alias tuple.
 ///
 /// This function checks the validity of the provided JWS and ensures it matches the expected subject.
@@ -88,15 +164,37 @@ pub fn get_alias_tuple(
     })
 }
 
-/// Generates a deterministic seed for a given principal.
-///
-/// Note: In a real application, the salt should be randomized to ensure uniqueness.
-pub fn generate_seed(principal: &Principal) -> Hash {
-    let dummy_salt = [5u8; 32]; // Static salt for example purposes
+/// Generates the random salt used to derive canister signature seeds, unless one has been
+/// generated already, see `random::init_random_secret`.
+pub fn init_credential_salt() {
+    init_random_secret(
+        "credential salt",
+        || CREDENTIAL_SALT.with_borrow(|s| *s.get() != [0; 32]),
+        |salt| {
+            CREDENTIAL_SALT.with_borrow_mut(|s| {
+                s.set(salt).expect("Failed to save credential salt");
+            });
+        },
+    );
+}
+
+/// Returns the salt to derive canister signature seeds with. Returns an error until the random salt
+/// has been generated.
+pub fn credential_salt() -> Result<[u8; 32], IssueCredentialError> {
+    let salt = CREDENTIAL_SALT.with_borrow(|s| *s.get());
+    if salt == [0; 32] {
+        return Err(IssueCredentialError::Internal(
+            "Credential salt not generated yet, try again later".to_string(),
+        ));
+    }
+    Ok(salt)
+}
 
+/// Generates a deterministic seed for a given principal, using the given salt.
+pub fn generate_seed(salt: &[u8; 32], principal: &Principal) -> Hash {
     let mut bytes: Vec<u8> = Vec::with_capacity(32 + 1 + principal.as_slice().len() + 1);
-    bytes.push(dummy_salt.len() as u8);
-    bytes.extend_from_slice(&dummy_salt);
+    bytes.push(salt.len() as u8);
+    bytes.extend_from_slice(salt);
 
     let principal_bytes = principal.as_slice();
     bytes.push(principal_bytes.len() as u8);
@@ -109,10 +207,11 @@ pub fn generate_seed(principal: &Principal) -> Hash {
 ///
 /// This function generates a seed, creates a signing input and its hash, and updates the root hash.
 pub fn save_credential_hash(
+    salt: &[u8; 32],
     alias_tuple: &AliasTuple,
     credential_hash: Hash,
 ) -> Result<(), IssueCredentialError> {
-    let seed = generate_seed(&alias_tuple.id_alias);
+    let seed = generate_seed(salt, &alias_tuple.id_alias);
 
     SIGNATURES.with(|sigs| {
         let mut sigs = sigs.borrow_mut();
@@ -126,11 +225,12 @@ pub fn save_credential_hash(
 
 /// Creates a hash for the given credential JWT.
 pub fn create_credential_hash(
+    salt: &[u8; 32],
     alias_tuple: &AliasTuple,
     credential_jwt: &str,
 ) -> Result<Hash, IssueCredentialError> {
     let canister_id = ic_cdk::id();
-    let seed = generate_seed(&alias_tuple.id_alias);
+    let seed = generate_seed(salt, &alias_tuple.id_alias);
     let canister_sig_pk = CanisterSigPublicKey::new(canister_id, seed.to_vec());
 
     // Compute the signing input and handle any errors that occur.
@@ -146,10 +246,11 @@ pub fn create_credential_hash(
 
 /// Retrieves the signature for a given credential hash.
 pub fn get_signature(
+    salt: &[u8; 32],
     alias_tuple: &AliasTuple,
     credential_hash: Hash,
 ) -> Result<Vec<u8>, IssueCredentialError> {
-    let seed = generate_seed(&alias_tuple.id_alias);
+    let seed = generate_seed(salt, &alias_tuple.id_alias);
 
     SIGNATURES
         .with(|sigs| {
@@ -164,12 +265,13 @@ pub fn get_signature(
 
 /// Creates a JWS (JSON Web Signature) for the given credential JWT.
 pub fn create_jws(
+    salt: &[u8; 32],
     alias_tuple: &AliasTuple,
     credential_jwt: &str,
     sig: &[u8],
 ) -> Result<String, IssueCredentialError> {
     let canister_id = ic_cdk::id();
-    let seed = generate_seed(&alias_tuple.id_alias);
+    let seed = generate_seed(salt, &alias_tuple.id_alias);
 
     let canister_sig_pk = CanisterSigPublicKey::new(canister_id, seed.to_vec());
 
@@ -197,13 +299,76 @@ pub fn validate_score(
     Ok(())
 }
 
+/// Returns the score record to issue a credential for, only considering scores that are recent
+/// enough, see `is_score_fresh`. Under the `Max` policy, a stale score does not hide a lower score
+/// of another address that is still fresh.
+pub fn credential_score(
+    principal: &StorablePrincipal,
+) -> Result<ScoreRecord, IssueCredentialError> {
+    let max_age = SETTINGS.with_borrow(|settings_opt| {
+        settings_opt
+            .as_ref()
+            .expect("Settings should be initialized")
+            .credential_max_score_age_ns
+    });
+    let now = time();
+
+    if let Some(record) =
+        effective_score_of(principal, |record| is_score_fresh(record, now, max_age))
+    {
+        return Ok(record);
+    }
+
+    match effective_score(principal) {
+        Some(_) => Err(IssueCredentialError::UnauthorizedSubject(
+            "Score is outdated, refresh the score and try again".to_string(),
+        )),
+        None => Err(IssueCredentialError::UnauthorizedSubject(
+            "No score registered for principal".to_string(),
+        )),
+    }
+}
+
+/// Whether a score is recent enough to issue a credential for.
+///
+/// A score is stale when it has expired according to the score provider, or when it was fetched
+/// longer ago than the configured maximum score age. Scores migrated from before fetch times were
+/// recorded are always stale when a maximum score age is configured.
+fn is_score_fresh(record: &ScoreRecord, now: u64, max_age: Option<u64>) -> bool {
+    let expired = record
+        .expires_at
+        .is_some_and(|expires_at| expires_at <= now);
+    let too_old = max_age.is_some_and(|max_age| record.fetched_at.saturating_add(max_age) <= now);
+    !expired && !too_old
+}
+
+/// Creates a unique id for a credential, in the form of a random (version 4) UUID URN.
+pub async fn create_credential_id() -> Result<String, IssueCredentialError> {
+    let (mut random_bytes,) = raw_rand().await.map_err(|(_, m)| {
+        IssueCredentialError::Internal(format!("Failed to generate credential id: {}", m))
+    })?;
+    random_bytes[6] = (random_bytes[6] & 0x0f) | 0x40; // Version 4
+    random_bytes[8] = (random_bytes[8] & 0x3f) | 0x80; // RFC 4122 variant
+
+    Ok(format!(
+        "urn:uuid:{}-{}-{}-{}-{}",
+        hex::encode(&random_bytes[0..4]),
+        hex::encode(&random_bytes[4..6]),
+        hex::encode(&random_bytes[6..8]),
+        hex::encode(&random_bytes[8..10]),
+        hex::encode(&random_bytes[10..16])
+    ))
+}
+
 /// Creates a JWT for the credential.
 ///
 /// Encapsulates the entire process of creating a JWT for a credential, from building the subject JSON
 /// to setting the expiration timestamp, ensuring all details are handled in a single, focused function.
+/// The issuer is the URL configured in settings.
 pub fn create_credential_jwt(
     credential_spec: &CredentialSpec,
     alias_tuple: &AliasTuple,
+    credential_id: &str,
 ) -> Result<String, IssueCredentialError> {
     let mut subject_json = json!({"id": did_for_principal(alias_tuple.id_alias)});
     let min_score = get_credential_min_score(credential_spec)?;
@@ -213,18 +378,27 @@ pub fn create_credential_jwt(
     );
     let subject = Subject::from_json_value(subject_json).unwrap();
 
-    let expiration_seconds = ((time() + VC_EXPIRATION_PERIOD_NS) / 1_000_000_000) as i64;
+    let validity_ns = get_credential_validity_ns(credential_spec)?;
+    let expiration_seconds = ((time() + validity_ns) / 1_000_000_000) as i64;
     let expiration_timestamp = Timestamp::from_unix(expiration_seconds).map_err(|e| {
         IssueCredentialError::Internal(format!("Failed computing expiration timestamp: {}", e))
     })?;
 
+    let issuer_url = SETTINGS.with_borrow(|settings_opt| {
+        settings_opt
+            .as_ref()
+            .expect("Settings should be initialized")
+            .issuer_url
+            .clone()
+    });
+    let issuer_url = Url::parse(issuer_url)
+        .map_err(|e| IssueCredentialError::Internal(format!("Invalid issuer URL: {}", e)))?;
+    let credential_id = Url::parse(credential_id)
+        .map_err(|e| IssueCredentialError::Internal(format!("Invalid credential id: {}", e)))?;
+
     let credential: Credential = CredentialBuilder::default()
-        .id(Url::parse(format!(
-            "https://ycons-daaaa-aaaal-qja3q-cai.icp0.io/{}",
-            min_score
-        ))
-        .unwrap())
-        .issuer(Url::parse("https://ycons-daaaa-aaaal-qja3q-cai.icp0.io").unwrap())
+        .id(credential_id)
+        .issuer(issuer_url)
         .type_(credential_spec.credential_type.clone())
         .subject(subject)
         .expiration_date(expiration_timestamp)
@@ -5,19 +5,37 @@ import { queryClient } from "../main";
 import toast from "react-hot-toast";
 import { useEffect } from "react";
 import { useInternetIdentity } from "ic-use-internet-identity";
+import { useIssuerBackend } from "../issuer_backend/IssuerBackendProvider";
 import { useLinkScore } from "../issuer_backend/hooks/useLinkScore";
 import { useScore } from "../issuer_backend/hooks/useScore";
 
-function createLinkSignatureMessage(
+function createLinkSiweMessage(
   address: `0x${string}`,
   principal: Principal,
+  chainId: number,
+  nonce: string,
 ) {
-  return `Sign this message to link your Ethereum address to your Internet Computer identity.\n\nEthereum address: ${address}\n\nInternet Computer principal: ${principal.toText()}`;
+  const issuedAt = new Date();
+  const expirationTime = new Date(issuedAt.getTime() + 5 * 60 * 1000);
+  return [
+    `${window.location.host} wants you to sign in with your Ethereum account:`,
+    address,
+    "",
+    `Sign in to link your Ethereum address to Internet Computer principal ${principal.toText()}.`,
+    "",
+    `URI: ${window.location.origin}`,
+    "Version: 1",
+    `Chain ID: ${chainId}`,
+    `Nonce: ${nonce}`,
+    `Issued At: ${issuedAt.toISOString()}`,
+    `Expiration Time: ${expirationTime.toISOString()}`,
+  ].join("\n");
 }
 
 export default function ScoreButton() {
   const { address } = useAccount();
   const { identity } = useInternetIdentity();
+  const { actor: issuerBackend } = useIssuerBackend();
   const { signMessage, isPending: isSignaturePending } = useSignMessage();
   const {
     mutate: linkScore,
@@ -43,7 +61,20 @@ export default function ScoreButton() {
   useEffect(() => {
     if (!isSuccess) return;
     if ("Err" in data) {
-      toast.error("Error linking score: " + data.Err);
+      if (
+        "ScoreProvider" in data.Err &&
+        "Processing" in data.Err.ScoreProvider
+      ) {
+        toast("Your score is being calculated, check back in a minute");
+        return;
+      }
+      if ("RateLimited" in data.Err) {
+        toast.error(
+          `Too many attempts, try again in ${data.Err.RateLimited.retry_after_secs} seconds`,
+        );
+        return;
+      }
+      toast.error("Error linking score: " + JSON.stringify(data.Err));
       return;
     }
     toast.success("Score linked");
@@ -56,16 +87,26 @@ export default function ScoreButton() {
   }, [isError, error]);
 
   const register = async () => {
-    if (!address || !identity) return;
-    const message = createLinkSignatureMessage(
+    if (!address || !identity || !issuerBackend) return;
+    // The message has to specify the chain ID configured in the issuer, whichever chain the
+    // wallet is connected to.
+    const chainId = await issuerBackend.link_chain_id_get();
+    const nonceResponse = await issuerBackend.link_nonce_create();
+    if ("Err" in nonceResponse) {
+      toast.error("Error linking score: " + nonceResponse.Err);
+      return;
+    }
+    const message = createLinkSiweMessage(
       address,
       identity.getPrincipal(),
+      Number(chainId),
+      nonceResponse.Ok,
     );
     signMessage(
       { message },
       {
         onSuccess(signature) {
-          linkScore({ address, signature });
+          linkScore({ message, signature });
         },
       },
     );
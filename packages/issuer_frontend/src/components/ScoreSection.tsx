@@ -14,7 +14,7 @@ export default function ScoreSection() {
       <h2>Linked Gitcoin Passport Score</h2>
       <div className="credential">
         {scoreResponse && "Ok" in scoreResponse ? (
-          <div className="score">{scoreResponse.Ok.toFixed(2)}</div>
+          <div className="score">{scoreResponse.Ok.score.toFixed(2)}</div>
         ) : (
           <div className="score">–</div>
         )}
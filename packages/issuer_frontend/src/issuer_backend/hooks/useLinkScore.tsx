@@ -5,14 +5,14 @@ export const useLinkScore = () => {
   const { actor: issuerBackend } = useIssuerBackend();
   return useMutation({
     mutationFn: ({
-      address,
+      message,
       signature,
     }: {
-      address: string;
+      message: string;
       signature: string;
     }) => {
       if (!issuerBackend) throw new Error("Issuer backend not available");
-      return issuerBackend.score_link(signature, address);
+      return issuerBackend.score_link(message, signature);
     },
   });
 };